@@ -0,0 +1,417 @@
+//! Command segments.
+//!
+//! A command segment shows the output of a shell command run in the current
+//! directory. The command is killed when it runs past its timeout, so it
+//! never blocks the prompt, and its result is cached on disk, keyed by the
+//! command and the directory, so a slow command runs at most once per cache
+//! TTL. When the command times out, it is killed together with every process
+//! it started, and the last cached result is shown instead. A timeout is
+//! cached as well, so a hanging command is not run again on every prompt.
+//! Cache entries that have not been updated for longer than the cache TTL
+//! are pruned, but kept for at least a day as a fallback for timeouts.
+
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::CommandConfig;
+
+/// Interval at which a command that closed its output is checked for exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Minimum time before a command that timed out runs again.
+const TIMEOUT_RETRY: Duration = Duration::from_secs(30);
+
+/// Minimum age of a pruned cache entry, so that a command that times out
+/// still has recent output to fall back to.
+const STALE_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Interval between two prunes of the cache directory.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// File in the cache directory whose modification time records the last
+/// prune.
+const PRUNE_MARKER: &str = ".pruned";
+
+/// Result of a command run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CommandOutput {
+    /// Exit status, or `None` if the command was killed by a signal.
+    pub status: Option<i32>,
+    /// First line of the trimmed standard output.
+    pub text: String,
+    /// The last run timed out; `status` and `text` are from the run before.
+    #[serde(default)]
+    pub timed_out: bool,
+}
+
+/// The shell command of a command segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentCommand {
+    run: String,
+    timeout: Duration,
+    cache_ttl: Duration,
+    per_directory: bool,
+}
+
+impl SegmentCommand {
+    pub fn from_config(config: &CommandConfig) -> Self {
+        Self {
+            run: config.run.clone(),
+            timeout: config.timeout(),
+            cache_ttl: config.cache_ttl(),
+            per_directory: config.per_directory(),
+        }
+    }
+
+    /// Output of the command run in `cwd`: a cached result younger than the
+    /// cache TTL, otherwise a fresh run, which is cached in `cache_dir`. If
+    /// the command times out or can't be started, the last cached result of
+    /// any age is returned, and the command is not run again for at least
+    /// [`TIMEOUT_RETRY`].
+    pub fn output(&self, cwd: &Path, cache_dir: Option<&Path>) -> Option<CommandOutput> {
+        let cache_path = cache_dir.map(|dir| dir.join(self.cache_key(cwd)));
+        let cached = cache_path.as_deref().and_then(read_cache);
+        if let Some((output, age)) = &cached {
+            let ttl = match output.timed_out {
+                true => self.cache_ttl.max(TIMEOUT_RETRY),
+                false => self.cache_ttl,
+            };
+            if *age < ttl {
+                return Some(output.clone());
+            }
+        }
+        let stale = cached.map(|(output, _)| output);
+        match run_with_timeout(&self.run, cwd, self.timeout) {
+            Some(output) => {
+                if let Some(path) = &cache_path {
+                    write_cache(path, &output);
+                }
+                Some(output)
+            }
+            None => {
+                if let Some(path) = &cache_path {
+                    let timed_out = CommandOutput {
+                        timed_out: true,
+                        ..stale.clone().unwrap_or_default()
+                    };
+                    write_cache(path, &timed_out);
+                }
+                stale
+            }
+        }
+    }
+
+    /// How long a result is reused.
+    pub fn cache_ttl(&self) -> Duration {
+        self.cache_ttl
+    }
+
+    /// Cache file name: a hash of the command and, for commands that depend
+    /// on it, the directory.
+    fn cache_key(&self, cwd: &Path) -> String {
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, self.run.as_bytes());
+        if self.per_directory {
+            hash = fnv1a(hash, &[0]);
+            hash = fnv1a(hash, cwd.as_os_str().as_encoded_bytes());
+        }
+        format!("{:016x}", hash)
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hash of `bytes`, continuing from `hash`. Unlike
+/// `DefaultHasher`, it gives the same cache file names with every
+/// toolchain.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Directory of the command cache, `pulse/commands` in the user cache
+/// directory.
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("pulse").join("commands"))
+}
+
+/// Remove the cache entries in `dir` that have not been updated for longer
+/// than `cache_ttl`, the longest cache TTL of the command segments, and at
+/// least [`STALE_RETENTION`]. Runs at most once per [`PRUNE_INTERVAL`].
+pub fn prune_cache(dir: &Path, cache_ttl: Duration) {
+    let marker = dir.join(PRUNE_MARKER);
+    if age(&marker).is_some_and(|age| age < PRUNE_INTERVAL) || std::fs::write(&marker, "").is_err()
+    {
+        return;
+    }
+    let max_age = cache_ttl.max(STALE_RETENTION);
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path != marker && age(&path).is_some_and(|age| age > max_age) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Time since `path` was last modified.
+fn age(path: &Path) -> Option<Duration> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.elapsed().unwrap_or_default())
+}
+
+/// Read a cached result and its age.
+fn read_cache(path: &Path) -> Option<(CommandOutput, Duration)> {
+    let age = age(path)?;
+    let output = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    Some((output, age))
+}
+
+/// Cache a result. Write errors are ignored: the command then simply runs
+/// again next time.
+fn write_cache(path: &Path, output: &CommandOutput) {
+    let Some(dir) = path.parent() else {
+        return;
+    };
+    let Ok(content) = serde_json::to_vec(output) else {
+        return;
+    };
+    // Write to a temporary file first so that concurrent prompts never read
+    // a partial result.
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    if std::fs::create_dir_all(dir).is_ok() && std::fs::write(&temp_path, content).is_ok() {
+        let _ = std::fs::rename(&temp_path, path);
+    }
+}
+
+/// Run `command` with `sh -c` in `cwd`, killing it if it has not exited
+/// after `timeout`.
+fn run_with_timeout(command: &str, cwd: &Path, timeout: Duration) -> Option<CommandOutput> {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]).current_dir(cwd);
+    let (status, bytes) = output_with_timeout(&mut shell, timeout)?;
+
+    let output = String::from_utf8_lossy(&bytes);
+    Some(CommandOutput {
+        status: status.code(),
+        text: output
+            .trim()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        timed_out: false,
+    })
+}
+
+/// Run `command` and capture its stdout, or `None` if it cannot be started
+/// or does not finish within `timeout`, in which case it is killed along
+/// with every process it started.
+pub fn output_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+) -> Option<(ExitStatus, Vec<u8>)> {
+    let deadline = Instant::now() + timeout;
+    // A process group of its own lets a timeout kill every process the
+    // command started, not just the command.
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .ok()?;
+    let mut stdout = child.stdout.take()?;
+
+    // The output is read on a thread of its own, which is abandoned if the
+    // command outlives its timeout.
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = stdout.read_to_end(&mut bytes);
+        let _ = sender.send(bytes);
+    });
+    let finished = receiver
+        .recv_timeout(timeout)
+        .ok()
+        .and_then(|bytes| Some((wait_until(&mut child, deadline)?, bytes)));
+    if finished.is_none() {
+        kill_process_group(&mut child);
+    }
+    finished
+}
+
+/// Kill `child` and every process in its process group, and reap it.
+fn kill_process_group(child: &mut Child) {
+    // SAFETY: `kill` has no memory-safety preconditions; a negative pid
+    // names the process group the child leads.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+/// Wait for `child` to exit, giving up at `deadline`.
+fn wait_until(child: &mut Child, deadline: Instant) -> Option<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait().ok()? {
+            return Some(status);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn command(run: &str, timeout: &str, cache_ttl: &str) -> SegmentCommand {
+        SegmentCommand::from_config(&CommandConfig {
+            run: run.to_string(),
+            timeout: Some(timeout.to_string()),
+            cache_ttl: Some(cache_ttl.to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_run_with_timeout() {
+        let cwd = TempDir::new().expect("temp dir");
+        let run = |command| run_with_timeout(command, cwd.path(), Duration::from_secs(5));
+
+        assert_eq!(
+            run("printf '  on-call: alice \\nbob\\n'"),
+            Some(CommandOutput {
+                status: Some(0),
+                text: "on-call: alice".to_string(),
+                timed_out: false,
+            })
+        );
+        assert_eq!(
+            run("echo down; exit 3"),
+            Some(CommandOutput {
+                status: Some(3),
+                text: "down".to_string(),
+                timed_out: false,
+            })
+        );
+
+        // The commands the shell started are killed with it.
+        let late = cwd.path().join("late");
+        let start = Instant::now();
+        let output = run_with_timeout(
+            &format!("sleep 0.5; touch {}", late.display()),
+            cwd.path(),
+            Duration::from_millis(100),
+        );
+        assert_eq!(output, None);
+        assert!(start.elapsed() < Duration::from_millis(400));
+        std::thread::sleep(Duration::from_secs(1));
+        assert!(!late.exists());
+    }
+
+    #[test]
+    fn test_output_is_cached_per_directory() {
+        let cache = TempDir::new().expect("temp dir");
+        let cwd = TempDir::new().expect("temp dir");
+        let other = TempDir::new().expect("temp dir");
+        // Counts its own runs in the directory it runs in.
+        let counter = "echo run >> runs; wc -l < runs";
+        let text = |command: &SegmentCommand, cwd: &Path| {
+            command
+                .output(cwd, Some(cache.path()))
+                .map(|output| output.text)
+        };
+
+        let cached = command(counter, "5s", "1h");
+        assert_eq!(text(&cached, cwd.path()).as_deref(), Some("1"));
+        assert_eq!(text(&cached, cwd.path()).as_deref(), Some("1"));
+        assert_eq!(text(&cached, other.path()).as_deref(), Some("1"));
+
+        let uncached = command(counter, "5s", "0s");
+        assert_eq!(text(&uncached, cwd.path()).as_deref(), Some("2"));
+        assert_eq!(text(&uncached, cwd.path()).as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn test_cache_key_is_stable() {
+        let cwd = Path::new("/home/dev/src");
+        let command = command("git config user.email", "1s", "0s");
+        assert_eq!(command.cache_key(cwd), "d74cdd419de54163");
+        assert_ne!(command.cache_key(cwd), command.cache_key(Path::new("/srv")));
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_prune_cache() {
+        let cache = TempDir::new().expect("temp dir");
+        let old = cache.path().join("old");
+        let recent = cache.path().join("recent");
+        for path in [&old, &recent] {
+            std::fs::write(path, "{}").expect("write entry");
+        }
+        let two_days_ago = std::time::SystemTime::now() - 2 * STALE_RETENTION;
+        std::fs::File::options()
+            .write(true)
+            .open(&old)
+            .and_then(|file| file.set_modified(two_days_ago))
+            .expect("set mtime");
+
+        prune_cache(cache.path(), Duration::ZERO);
+        assert!(!old.exists());
+        assert!(recent.exists());
+
+        // The next prune is skipped until the prune interval has passed.
+        std::fs::write(&old, "{}").expect("write entry");
+        std::fs::File::options()
+            .write(true)
+            .open(&old)
+            .and_then(|file| file.set_modified(two_days_ago))
+            .expect("set mtime");
+        prune_cache(cache.path(), Duration::ZERO);
+        assert!(old.exists());
+    }
+
+    #[test]
+    fn test_timeout_falls_back_to_cached_output() {
+        let cache = TempDir::new().expect("temp dir");
+        let cwd = TempDir::new().expect("temp dir");
+        let flag = cwd.path().join("slow");
+        let command = command(
+            &format!("test -e {} && sleep 5; echo ready", flag.display()),
+            "300ms",
+            "0s",
+        );
+
+        let output = command.output(cwd.path(), Some(cache.path()));
+        assert_eq!(output.map(|output| output.text).as_deref(), Some("ready"));
+
+        std::fs::write(&flag, "").expect("write flag");
+        let output = command.output(cwd.path(), Some(cache.path()));
+        assert_eq!(output.map(|output| output.text).as_deref(), Some("ready"));
+
+        // The timeout is cached, so the command doesn't run again right away.
+        let start = Instant::now();
+        let output = command.output(cwd.path(), Some(cache.path()));
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(
+            output.map(|output| (output.text, output.timed_out)),
+            Some(("ready".to_string(), true))
+        );
+        assert_eq!(command.output(cwd.path(), None), None);
+    }
+}
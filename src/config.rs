@@ -8,8 +8,10 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::clrs::Clrs;
+use crate::template::{Template, VARIABLES};
 
 /// Configuration for a single prompt segment.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -20,20 +22,557 @@ pub struct SegmentConfig {
     /// When not specified, Pulse uses terminal ANSI colors that adapt to your
     /// terminal's configured color palette.
     pub color: Option<String>,
+    /// Text placed between this segment and the one rendered before it.
+    /// Defaults to the separator of the built-in layout, e.g. `@` between
+    /// the username and the hostname.
+    pub separator: Option<String>,
+    /// Set to `false` to remove the segment, e.g. one added by the defaults
+    /// or the system config.
+    pub enabled: Option<bool>,
+    /// Makes this a command segment, showing the output of a shell command.
+    /// Its name is then free, but must not be a built-in segment name.
+    pub command: Option<CommandConfig>,
+    /// Makes this an env segment, showing the value of an environment
+    /// variable. Like a command segment, it needs a name of its own.
+    pub env: Option<EnvConfig>,
+}
+
+impl SegmentConfig {
+    /// Whether the segment is shown; segments are enabled by default.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Kind of the segment: a command segment when `command` is set, an env
+    /// segment when `env` is set, otherwise the built-in segment called
+    /// `name`. Command and env segments become template variables, so their
+    /// names must not shadow a built-in segment or variable.
+    pub fn kind(&self) -> Result<SegmentKind> {
+        let (kind, label) = match (&self.command, &self.env) {
+            (None, None) => return self.name.parse(),
+            (Some(_), None) => (SegmentKind::Command, "Command"),
+            (None, Some(_)) => (SegmentKind::Env, "Env"),
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "Segment {} can't have both a command and an env variable",
+                    self.name
+                ));
+            }
+        };
+        if self.name.is_empty() {
+            return Err(anyhow!("{} segment needs a name", label));
+        }
+        if self.name.parse::<SegmentKind>().is_ok() {
+            return Err(anyhow!(
+                "{} segment uses a built-in segment name: {}",
+                label,
+                self.name
+            ));
+        }
+        if VARIABLES.contains(&self.name.as_str()) {
+            return Err(anyhow!(
+                "{} segment uses a template variable name: {}",
+                label,
+                self.name
+            ));
+        }
+        Ok(kind)
+    }
+}
+
+/// Options of a command segment.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct CommandConfig {
+    /// Shell command, run with `sh -c` in the current directory. The first
+    /// line of its trimmed output is shown; empty output hides the segment.
+    pub run: String,
+    /// Time the command may take, e.g. `300ms`; defaults to 200ms.
+    pub timeout: Option<String>,
+    /// How long a result is reused before the command runs again, e.g.
+    /// `5m`; by default it runs on every prompt.
+    pub cache_ttl: Option<String>,
+    /// Whether the output depends on the current directory, so results are
+    /// cached per directory; on by default.
+    pub per_directory: Option<bool>,
+    /// Colors of the output by exit status; the segment color is used for
+    /// other statuses.
+    pub status_colors: Option<Vec<StatusColorRule>>,
+}
+
+impl CommandConfig {
+    /// Get the command timeout.
+    ///
+    /// Invalid values fall back to the default; [`Config::validate`] rejects
+    /// them when a config file is loaded.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+            .as_deref()
+            .and_then(|value| parse_duration(value).ok())
+            .unwrap_or(DEFAULT_COMMAND_TIMEOUT)
+    }
+
+    /// Get how long a cached result is reused; zero by default.
+    pub fn cache_ttl(&self) -> Duration {
+        self.cache_ttl
+            .as_deref()
+            .and_then(|value| parse_duration(value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether results are cached per directory; on by default.
+    pub fn per_directory(&self) -> bool {
+        self.per_directory.unwrap_or(true)
+    }
+}
+
+/// Maps an exit status of a command segment to a color and text style.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StatusColorRule {
+    /// Exit status of the command, e.g. `0` or `1`.
+    pub status: i32,
+    /// Color of the output; defaults to the segment color.
+    pub color: Option<String>,
+    /// Text style: "bold", "dimmed", "italic" or "underline".
+    pub style: Option<String>,
+}
+
+/// Options of an env segment.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct EnvConfig {
+    /// Name of the environment variable, e.g. `DEPLOY_ENV`.
+    pub var: String,
+    /// Text shown, with `{value}` replaced by the value, e.g. `env:{value}`;
+    /// defaults to the value alone.
+    pub format: Option<String>,
+    /// Display text and color for specific values, e.g. `prod` shown as a
+    /// red `PROD`.
+    pub values: Option<Vec<EnvValueRule>>,
+    /// When the segment is hidden: "unset", "empty" (unset or empty, the
+    /// default) or "never".
+    pub hide: Option<String>,
+}
+
+impl EnvConfig {
+    /// Get when the segment is hidden.
+    ///
+    /// Invalid values fall back to the default; [`Config::validate`] rejects
+    /// them when a config file is loaded.
+    pub fn hide(&self) -> EnvHide {
+        self.hide
+            .as_deref()
+            .and_then(|hide| hide.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Get the format string.
+    pub fn format(&self) -> &str {
+        self.format.as_deref().unwrap_or("{value}")
+    }
+}
+
+/// Maps a value of an env segment's variable to a display text and color.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EnvValueRule {
+    /// Value of the variable, e.g. `prod`.
+    pub value: String,
+    /// Text shown instead of the value, e.g. `PROD`.
+    pub text: Option<String>,
+    /// Color of the segment; defaults to the segment color.
+    pub color: Option<String>,
+    /// Text style: "bold", "dimmed", "italic" or "underline".
+    pub style: Option<String>,
+}
+
+/// When an env segment is hidden.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnvHide {
+    /// Only when the variable is unset.
+    Unset,
+    /// When the variable is unset or empty.
+    #[default]
+    Empty,
+    /// Never; an unset variable is shown as an empty value.
+    Never,
+}
+
+impl std::str::FromStr for EnvHide {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "unset" => Ok(Self::Unset),
+            "empty" => Ok(Self::Empty),
+            "never" => Ok(Self::Never),
+            _ => Err(anyhow!("Invalid env segment hide rule: {}", s)),
+        }
+    }
+}
+
+/// Whether `name` is a valid environment variable name: ASCII letters,
+/// digits and underscores, not starting with a digit.
+fn is_env_var_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A segment of the prompt line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// The login name, or the local part of `user.email` in a repository.
+    Username,
+    /// The machine name, or the domain of `user.email` in a repository.
+    Hostname,
+    /// The current directory, relative to the work tree in a repository.
+    CurrentDirectory,
+    /// The git block, e.g. `[pulse : main]`; empty outside a repository.
+    GitBranch,
+    /// The output of a shell command.
+    Command,
+    /// The value of an environment variable.
+    Env,
+}
+
+impl SegmentKind {
+    /// Segments every layout must contain.
+    pub const REQUIRED: [Self; 1] = [Self::CurrentDirectory];
+
+    /// Name of a built-in segment in the config file, or the type of a
+    /// command or env segment.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Username => "username",
+            Self::Hostname => "hostname",
+            Self::CurrentDirectory => "current_directory",
+            Self::GitBranch => "git_branch",
+            Self::Command => "command",
+            Self::Env => "env",
+        }
+    }
+}
+
+impl std::str::FromStr for SegmentKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "username" => Ok(Self::Username),
+            "hostname" => Ok(Self::Hostname),
+            "current_directory" => Ok(Self::CurrentDirectory),
+            "git_branch" => Ok(Self::GitBranch),
+            _ => Err(anyhow!("Invalid segment name: {}", s)),
+        }
+    }
 }
 
 /// Main configuration structure for Pulse.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
-    /// List of segment configurations.
+    /// Segments of the prompt line, in display order.
     pub segments: Vec<SegmentConfig>,
     /// Display mode: "DualLine" or "Inline".
     pub mode: Option<String>,
+    /// Template laying out the whole prompt, e.g.
+    /// `{line}\n└─ {exit} {symbol} `; overrides `mode`.
+    pub template: Option<String>,
+    /// Options for the git block.
+    #[serde(default)]
+    pub git: GitConfig,
     /// Cached color lookup for O(1) access.
     #[serde(skip)]
     pub segment_colors: HashMap<String, Clrs>,
 }
 
+/// Options that control how the git block is resolved.
+///
+/// Every field is optional so that a higher-precedence config only overrides
+/// the options it explicitly sets.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct GitConfig {
+    /// Order in which a detached HEAD is described, e.g.
+    /// `["exact_tag", "nearest_tag", "remote_branch"]`. The short hash is
+    /// always used as the last fallback.
+    pub detached_head: Option<Vec<String>>,
+    /// Order in which the repository name is resolved, e.g.
+    /// `["workspace_member", "remote_url"]`. The directory name is always
+    /// used as the last fallback.
+    pub repo_name: Option<Vec<String>>,
+    /// Expected identities; the first rule that applies to a repository is
+    /// checked against `user.email`.
+    pub identity: Option<Vec<IdentityRule>>,
+    /// Transforms applied to the branch name before it is displayed.
+    #[serde(default)]
+    pub branch: BranchConfig,
+    /// Colors for branches matching a pattern; the first matching rule wins.
+    pub branch_colors: Option<Vec<BranchColorRule>>,
+    /// Age of `FETCH_HEAD` after which the stale fetch marker is shown, e.g.
+    /// `12h` or `2d`.
+    pub stale_fetch_after: Option<String>,
+    /// Maximum number of submodules inspected for the submodule summary;
+    /// `0` disables the summary.
+    pub submodule_limit: Option<usize>,
+    /// Show how far HEAD has diverged from the default branch.
+    pub default_branch_distance: Option<bool>,
+    /// Where and how repositories are discovered.
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    /// Treat repositories owned by other users as untrusted unless git's
+    /// `safe.directory` lists them; on by default.
+    pub safe_mode: Option<bool>,
+    /// Time the slow parts of the git block (status, upstream, default
+    /// branch distance, submodules, stashes, repository name lookups, the
+    /// description of a detached HEAD and `jj log`) may take per render, e.g.
+    /// `150ms`. Unbounded by default.
+    pub time_budget: Option<String>,
+}
+
+/// Limits on repository discovery.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct DiscoveryConfig {
+    /// Globs for directories in which no repository is looked up, e.g.
+    /// `/net/*`. Subdirectories of a matching directory are excluded too.
+    pub exclude: Option<Vec<String>>,
+    /// How repositories on network and FUSE filesystems are read: "skip",
+    /// "branch" or "full".
+    pub slow_filesystems: Option<String>,
+}
+
+impl DiscoveryConfig {
+    /// Get the configured exclusion globs.
+    pub fn exclude(&self) -> &[String] {
+        self.exclude.as_deref().unwrap_or_default()
+    }
+
+    /// Get how repositories on slow filesystems are read.
+    ///
+    /// Invalid values fall back to the default; [`Config::validate`] rejects
+    /// them when a config file is loaded.
+    pub fn slow_filesystems(&self) -> SlowFilesystemMode {
+        self.slow_filesystems
+            .as_deref()
+            .and_then(|mode| mode.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// How a repository on a network or FUSE filesystem is read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowFilesystemMode {
+    /// Show the prompt without the git block.
+    Skip,
+    /// Read only the branch from `HEAD`.
+    #[default]
+    Branch,
+    /// Discover the repository as on any other filesystem.
+    Full,
+}
+
+impl std::str::FromStr for SlowFilesystemMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "branch" => Ok(Self::Branch),
+            "full" => Ok(Self::Full),
+            _ => Err(anyhow!("Invalid slow filesystem mode: {}", s)),
+        }
+    }
+}
+
+/// Maps branch names to a color and text style.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BranchColorRule {
+    /// Glob matched against the displayed branch name, e.g. `release/*`.
+    pub pattern: String,
+    /// Color for matching branches; defaults to the `git_branch` color.
+    pub color: Option<String>,
+    /// Text style: "bold", "dimmed", "italic" or "underline".
+    pub style: Option<String>,
+}
+
+/// Branch name transforms, applied in field order.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct BranchConfig {
+    /// Prefixes removed from the start of the branch, e.g. `feature/`.
+    pub strip_prefixes: Option<Vec<String>>,
+    /// Regex extracting a ticket ID, e.g. `[A-Z]+-[0-9]+`. The first capture
+    /// group is used if there is one, otherwise the whole match.
+    pub ticket_pattern: Option<String>,
+    /// Maximum width in columns; longer names are truncated in the middle.
+    pub max_length: Option<usize>,
+}
+
+impl BranchConfig {
+    /// Whether any transform is configured.
+    pub fn is_active(&self) -> bool {
+        self.strip_prefixes.is_some() || self.ticket_pattern.is_some() || self.max_length.is_some()
+    }
+}
+
+/// Maps repositories to the `user.email` expected when working in them.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct IdentityRule {
+    /// Glob matched against the work tree path, e.g. `~/work/**`.
+    pub path: Option<String>,
+    /// Glob matched against remote URLs as `host/path`, e.g.
+    /// `github.com/ourcorp/*`.
+    pub remote: Option<String>,
+    /// Expected address, or a domain when it starts with `@`.
+    pub email: String,
+}
+
+/// A way of naming a detached HEAD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetachedHeadStrategy {
+    /// A tag that points directly at HEAD, e.g. `v1.4.2`.
+    ExactTag,
+    /// The closest tag reachable from HEAD plus the distance, e.g. `v1.4.2+3`.
+    NearestTag,
+    /// A remote-tracking branch that points at HEAD, e.g. `origin/main`.
+    RemoteBranch,
+}
+
+impl DetachedHeadStrategy {
+    /// The order used when `git.detached_head` is not configured.
+    pub const DEFAULT_ORDER: [Self; 3] = [Self::ExactTag, Self::NearestTag, Self::RemoteBranch];
+}
+
+impl std::str::FromStr for DetachedHeadStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exact_tag" => Ok(Self::ExactTag),
+            "nearest_tag" => Ok(Self::NearestTag),
+            "remote_branch" => Ok(Self::RemoteBranch),
+            _ => Err(anyhow!("Invalid detached HEAD strategy: {}", s)),
+        }
+    }
+}
+
+/// A way of naming the repository in the git block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoNameStrategy {
+    /// The name of the work tree directory (or of the main repository for a
+    /// linked worktree).
+    Directory,
+    /// `owner/repo` taken from the `origin` remote URL.
+    RemoteUrl,
+    /// The workspace member containing the current directory, detected from
+    /// `Cargo.toml`, `package.json` or `go.work`.
+    WorkspaceMember,
+}
+
+impl RepoNameStrategy {
+    /// The order used when `git.repo_name` is not configured.
+    pub const DEFAULT_ORDER: [Self; 1] = [Self::Directory];
+}
+
+impl std::str::FromStr for RepoNameStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "directory" => Ok(Self::Directory),
+            "remote_url" => Ok(Self::RemoteUrl),
+            "workspace_member" => Ok(Self::WorkspaceMember),
+            _ => Err(anyhow!("Invalid repo name strategy: {}", s)),
+        }
+    }
+}
+
+/// Stale fetch threshold used when `git.stale_fetch_after` is not configured.
+pub const DEFAULT_STALE_FETCH_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Submodules inspected when `git.submodule_limit` is not configured.
+pub const DEFAULT_SUBMODULE_LIMIT: usize = 20;
+
+/// Timeout of a command segment whose `timeout` is not configured.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Parse a duration like `200ms`, `90s`, `30m`, `12h`, `2d` or `1w`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let unit_start = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("Missing unit in duration: {}", s))?;
+    let (value, unit) = s.split_at(unit_start);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration: {}", s))?;
+    let unit_millis = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        "w" => 7 * 24 * 60 * 60 * 1000,
+        _ => return Err(anyhow!("Invalid duration unit: {}", s)),
+    };
+    value
+        .checked_mul(unit_millis)
+        .map(Duration::from_millis)
+        .ok_or_else(|| anyhow!("Duration too large: {}", s))
+}
+
+impl GitConfig {
+    /// Get the configured detached HEAD strategies, in order.
+    ///
+    /// Unknown names are skipped; [`Config::validate`] rejects them when a
+    /// config file is loaded.
+    pub fn detached_head_strategies(&self) -> Vec<DetachedHeadStrategy> {
+        match &self.detached_head {
+            Some(names) => names.iter().filter_map(|name| name.parse().ok()).collect(),
+            None => DetachedHeadStrategy::DEFAULT_ORDER.to_vec(),
+        }
+    }
+
+    /// Get the stale fetch threshold.
+    ///
+    /// Invalid values fall back to the default; [`Config::validate`] rejects
+    /// them when a config file is loaded.
+    pub fn stale_fetch_after(&self) -> Duration {
+        self.stale_fetch_after
+            .as_deref()
+            .and_then(|value| parse_duration(value).ok())
+            .unwrap_or(DEFAULT_STALE_FETCH_AFTER)
+    }
+
+    /// Get the maximum number of submodules inspected for the summary.
+    pub fn submodule_limit(&self) -> usize {
+        self.submodule_limit.unwrap_or(DEFAULT_SUBMODULE_LIMIT)
+    }
+
+    /// Whether the distance from the default branch is shown; off by default.
+    pub fn default_branch_distance(&self) -> bool {
+        self.default_branch_distance.unwrap_or(false)
+    }
+
+    /// Get the time budget of the slow indicators, if any.
+    ///
+    /// Invalid values disable the budget; [`Config::validate`] rejects them
+    /// when a config file is loaded.
+    pub fn time_budget(&self) -> Option<Duration> {
+        self.time_budget
+            .as_deref()
+            .and_then(|value| parse_duration(value).ok())
+    }
+
+    /// Whether repositories owned by other users are untrusted; on by
+    /// default.
+    pub fn safe_mode(&self) -> bool {
+        self.safe_mode.unwrap_or(true)
+    }
+
+    /// Get the configured repository name strategies, in order.
+    pub fn repo_name_strategies(&self) -> Vec<RepoNameStrategy> {
+        match &self.repo_name {
+            Some(names) => names.iter().filter_map(|name| name.parse().ok()).collect(),
+            None => RepoNameStrategy::DEFAULT_ORDER.to_vec(),
+        }
+    }
+}
+
 pub struct ConfigBuilder<State> {
     config: Config,
     _state: PhantomData<State>,
@@ -49,21 +588,39 @@ impl Default for Config {
                 SegmentConfig {
                     name: "username".to_string(),
                     color: Some("Blue".to_string()),
+                    separator: None,
+                    enabled: None,
+                    command: None,
+                    env: None,
                 },
                 SegmentConfig {
                     name: "hostname".to_string(),
                     color: Some("Green".to_string()),
-                },
-                SegmentConfig {
-                    name: "current_directory".to_string(),
-                    color: Some("Silver".to_string()),
+                    separator: None,
+                    enabled: None,
+                    command: None,
+                    env: None,
                 },
                 SegmentConfig {
                     name: "git_branch".to_string(),
                     color: Some("Red".to_string()),
+                    separator: None,
+                    enabled: None,
+                    command: None,
+                    env: None,
+                },
+                SegmentConfig {
+                    name: "current_directory".to_string(),
+                    color: Some("Silver".to_string()),
+                    separator: None,
+                    enabled: None,
+                    command: None,
+                    env: None,
                 },
             ],
             mode: Some("DualLine".to_string()),
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         }
     }
@@ -79,15 +636,19 @@ impl Config {
     ///
     /// When both the global and user configs define the same segment (by name),
     /// the user config takes precedence - the segment from the user config replaces
-    /// the corresponding segment from the global config. Duplicate segments within
-    /// a single config file are not supported; only the last occurrence would be kept
-    /// when parsed, though this depends on the YAML parser behavior. The display
-    /// mode is overridden only when the higher-precedence config explicitly sets it.
+    /// the corresponding segment from the global config, and a segment with
+    /// `enabled: false` removes it. A config that lists several existing segments
+    /// reorders them among their positions; see [`merge_segments`]. The display
+    /// mode and template are overridden only when the higher-precedence config
+    /// explicitly sets them.
     ///
     /// # Preconditions
     /// - The configuration files, if they exist, must be valid YAML.
-    /// - Segment names must be one of: "username", "hostname", "current_directory", "git_branch".
+    /// - Segment names must be one of: "username", "hostname", "current_directory", "git_branch",
+    ///   unless the segment is a command or env segment, and each may appear only once per file.
     /// - Mode must be one of: "DualLine", "Inline".
+    /// - The template must parse, and use only built-in variables and the names of command and
+    ///   env segments.
     /// - Colors must be valid color names parseable by [`std::str::FromStr`].
     ///
     /// # Postconditions
@@ -98,7 +659,8 @@ impl Config {
     /// Returns an error if:
     /// - A config file exists but cannot be read.
     /// - A config file contains invalid YAML.
-    /// - A config file contains invalid segment names or colors.
+    /// - A config file contains invalid or duplicate segment names, or invalid colors.
+    /// - The merged segments lack a required segment.
     ///
     /// # Example
     /// ```ignore
@@ -127,7 +689,7 @@ impl Config {
             builder = builder.merge_path_if_exists(&path)?;
         }
 
-        Ok(builder.build())
+        builder.build()
     }
 
     /// Load configuration from a specific file path.
@@ -138,7 +700,7 @@ impl Config {
     /// Returns an error if the explicit file path does not exist.
     pub fn load_from_path(path: &Path) -> Result<Self> {
         let builder = ConfigBuilder::new().with_defaults().merge_path(path)?;
-        Ok(builder.build())
+        builder.build()
     }
 
     fn build_color_cache(&mut self) {
@@ -154,25 +716,164 @@ impl Config {
 
     /// Validate the configuration for correctness.
     ///
-    /// Checks that all segment names are valid and colors parse correctly.
+    /// Checks that all segment names are valid and unique and colors parse
+    /// correctly.
     pub fn validate(&self) -> Result<()> {
-        let valid_names = ["username", "hostname", "current_directory", "git_branch"];
         let valid_modes = ["DualLine", "Inline"];
-        for segment in &self.segments {
-            if !valid_names.contains(&segment.name.as_str()) {
-                return Err(anyhow!("Invalid segment name: {}", segment.name));
+        let valid_styles = ["bold", "dimmed", "italic", "underline"];
+        for (index, segment) in self.segments.iter().enumerate() {
+            segment.kind()?;
+            if self.segments[..index]
+                .iter()
+                .any(|other| other.name == segment.name)
+            {
+                return Err(anyhow!("Duplicate segment: {}", segment.name));
             }
             if let Some(color_str) = &segment.color
                 && color_str.parse::<Clrs>().is_err()
             {
                 return Err(anyhow!("Invalid color: {}", color_str));
             }
+            if let Some(command) = &segment.command {
+                if command.run.trim().is_empty() {
+                    return Err(anyhow!("Command segment {} has no command", segment.name));
+                }
+                for value in command.timeout.iter().chain(&command.cache_ttl) {
+                    parse_duration(value)?;
+                }
+                for rule in command.status_colors.iter().flatten() {
+                    if let Some(color_str) = &rule.color
+                        && color_str.parse::<Clrs>().is_err()
+                    {
+                        return Err(anyhow!("Invalid color: {}", color_str));
+                    }
+                    if let Some(style) = &rule.style
+                        && !valid_styles.contains(&style.as_str())
+                    {
+                        return Err(anyhow!("Invalid style: {}", style));
+                    }
+                }
+            }
+            if let Some(env) = &segment.env {
+                if !is_env_var_name(&env.var) {
+                    return Err(anyhow!(
+                        "Env segment {} has an invalid variable name: {}",
+                        segment.name,
+                        env.var
+                    ));
+                }
+                if let Some(hide) = &env.hide {
+                    hide.parse::<EnvHide>()?;
+                }
+                for rule in env.values.iter().flatten() {
+                    if let Some(color_str) = &rule.color
+                        && color_str.parse::<Clrs>().is_err()
+                    {
+                        return Err(anyhow!("Invalid color: {}", color_str));
+                    }
+                    if let Some(style) = &rule.style
+                        && !valid_styles.contains(&style.as_str())
+                    {
+                        return Err(anyhow!("Invalid style: {}", style));
+                    }
+                }
+            }
         }
         if let Some(mode) = &self.mode
             && !valid_modes.contains(&mode.as_str())
         {
             return Err(anyhow!("Invalid mode: {}", mode));
         }
+        if let Some(template) = &self.template {
+            Template::parse(template)?;
+        }
+        if let Some(strategies) = &self.git.detached_head {
+            for strategy in strategies {
+                strategy.parse::<DetachedHeadStrategy>()?;
+            }
+        }
+        if let Some(strategies) = &self.git.repo_name {
+            for strategy in strategies {
+                strategy.parse::<RepoNameStrategy>()?;
+            }
+        }
+        if let Some(pattern) = &self.git.branch.ticket_pattern {
+            regex::Regex::new(pattern)
+                .map_err(|e| anyhow!("Invalid ticket pattern {}: {}", pattern, e))?;
+        }
+        if let Some(value) = &self.git.stale_fetch_after {
+            parse_duration(value)?;
+        }
+        if let Some(value) = &self.git.time_budget {
+            parse_duration(value)?;
+        }
+        for pattern in self.git.discovery.exclude() {
+            glob::Pattern::new(pattern)
+                .map_err(|e| anyhow!("Invalid discovery exclude pattern {}: {}", pattern, e))?;
+        }
+        if let Some(mode) = &self.git.discovery.slow_filesystems {
+            mode.parse::<SlowFilesystemMode>()?;
+        }
+        if self.git.branch.max_length == Some(0) {
+            return Err(anyhow!("Branch max_length must be at least 1"));
+        }
+        for rule in self.git.branch_colors.iter().flatten() {
+            glob::Pattern::new(&rule.pattern)
+                .map_err(|e| anyhow!("Invalid branch pattern {}: {}", rule.pattern, e))?;
+            if let Some(color_str) = &rule.color
+                && color_str.parse::<Clrs>().is_err()
+            {
+                return Err(anyhow!("Invalid color: {}", color_str));
+            }
+            if let Some(style) = &rule.style
+                && !valid_styles.contains(&style.as_str())
+            {
+                return Err(anyhow!("Invalid style: {}", style));
+            }
+        }
+        for rule in self.git.identity.iter().flatten() {
+            if rule.path.is_none() && rule.remote.is_none() {
+                return Err(anyhow!(
+                    "Identity rule for {} needs a path or remote pattern",
+                    rule.email
+                ));
+            }
+            for pattern in rule.path.iter().chain(&rule.remote) {
+                glob::Pattern::new(pattern)
+                    .map_err(|e| anyhow!("Invalid identity pattern {}: {}", pattern, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the segments, once merged from every source, contain the
+    /// required ones, and that the template uses only known variables.
+    pub fn validate_segments(&self) -> Result<()> {
+        for required in SegmentKind::REQUIRED {
+            if !self
+                .segments
+                .iter()
+                .any(|segment| segment.name == required.as_str())
+            {
+                return Err(anyhow!("Missing required segment: {}", required.as_str()));
+            }
+        }
+        if let Some(template) = &self.template {
+            let template = Template::parse(template)?;
+            for (name, position) in template.variables() {
+                let is_segment = self.segments.iter().any(|segment| {
+                    segment.name == name
+                        && matches!(segment.kind(), Ok(SegmentKind::Command | SegmentKind::Env))
+                });
+                if !VARIABLES.contains(&name) && !is_segment {
+                    return Err(anyhow!(
+                        "Invalid template at {}: unknown variable {}",
+                        position,
+                        name
+                    ));
+                }
+            }
+        }
         Ok(())
     }
 
@@ -203,6 +904,8 @@ impl ConfigBuilder<NeedsDefaults> {
             config: Config {
                 segments: Vec::new(),
                 mode: None,
+                template: None,
+                git: GitConfig::default(),
                 segment_colors: HashMap::new(),
             },
             _state: PhantomData,
@@ -236,9 +939,10 @@ impl ConfigBuilder<HasDefaults> {
         Ok(self)
     }
 
-    pub fn build(mut self) -> Config {
+    pub fn build(mut self) -> Result<Config> {
+        self.config.validate_segments()?;
         self.config.build_color_cache();
-        self.config
+        Ok(self.config)
     }
 }
 
@@ -274,19 +978,119 @@ fn user_config_path() -> Option<PathBuf> {
 }
 
 fn merge_configs(config: &mut Config, other: Config) {
-    for other_segment in other.segments {
-        if let Some(existing) = config
-            .segments
-            .iter_mut()
-            .find(|segment| segment.name == other_segment.name)
+    merge_segments(&mut config.segments, other.segments);
+    if other.mode.is_some() {
+        config.mode = other.mode;
+    }
+    if other.template.is_some() {
+        config.template = other.template;
+    }
+    merge_git_configs(&mut config.git, other.git);
+}
+
+/// Merge the segments of a higher-precedence config into `segments`.
+///
+/// Disabled segments are removed. The segments `other` lists that are
+/// already present replace them and take their positions in the order
+/// `other` lists them, so listing a single segment keeps the order and
+/// listing several can reorder them; segments `other` doesn't list stay
+/// where they are. A new segment is inserted after the segment listed
+/// before it, or before the first listed one, and appended when `other`
+/// lists no existing segment.
+fn merge_segments(segments: &mut Vec<SegmentConfig>, other: Vec<SegmentConfig>) {
+    let (listed, removed): (Vec<_>, Vec<_>) =
+        other.into_iter().partition(SegmentConfig::is_enabled);
+    segments.retain(|segment| !removed.iter().any(|other| other.name == segment.name));
+
+    // Each listed segment that is already present, followed by the new
+    // segments listed after it.
+    let mut leading = Vec::new();
+    let mut groups: Vec<Vec<SegmentConfig>> = Vec::new();
+    for segment in listed {
+        if segments
+            .iter()
+            .any(|existing| existing.name == segment.name)
         {
-            *existing = other_segment;
+            groups.push(vec![segment]);
+        } else if let Some(group) = groups.last_mut() {
+            group.push(segment);
         } else {
-            config.segments.push(other_segment);
+            leading.push(segment);
         }
     }
-    if other.mode.is_some() {
-        config.mode = other.mode;
+    if groups.is_empty() {
+        segments.extend(leading);
+        return;
+    }
+
+    let is_listed = segments
+        .iter()
+        .map(|segment| groups.iter().any(|group| group[0].name == segment.name))
+        .collect::<Vec<_>>();
+    let mut groups = groups.into_iter();
+    let mut merged = Vec::with_capacity(segments.len() + leading.len());
+    for (segment, is_listed) in std::mem::take(segments).into_iter().zip(is_listed) {
+        if is_listed {
+            // `leading` is only non-empty before the first listed position.
+            merged.append(&mut leading);
+            merged.extend(groups.next().into_iter().flatten());
+        } else {
+            merged.push(segment);
+        }
+    }
+    *segments = merged;
+}
+
+fn merge_git_configs(config: &mut GitConfig, other: GitConfig) {
+    if other.detached_head.is_some() {
+        config.detached_head = other.detached_head;
+    }
+    if other.repo_name.is_some() {
+        config.repo_name = other.repo_name;
+    }
+    if other.identity.is_some() {
+        config.identity = other.identity;
+    }
+    merge_branch_configs(&mut config.branch, other.branch);
+    if other.branch_colors.is_some() {
+        config.branch_colors = other.branch_colors;
+    }
+    if other.stale_fetch_after.is_some() {
+        config.stale_fetch_after = other.stale_fetch_after;
+    }
+    if other.submodule_limit.is_some() {
+        config.submodule_limit = other.submodule_limit;
+    }
+    if other.default_branch_distance.is_some() {
+        config.default_branch_distance = other.default_branch_distance;
+    }
+    merge_discovery_configs(&mut config.discovery, other.discovery);
+    if other.safe_mode.is_some() {
+        config.safe_mode = other.safe_mode;
+    }
+    if other.time_budget.is_some() {
+        config.time_budget = other.time_budget;
+    }
+}
+
+fn merge_discovery_configs(config: &mut DiscoveryConfig, other: DiscoveryConfig) {
+    if other.exclude.is_some() {
+        config.exclude = other.exclude;
+    }
+    if other.slow_filesystems.is_some() {
+        config.slow_filesystems = other.slow_filesystems;
+    }
+}
+
+fn merge_branch_configs(config: &mut BranchConfig, other: BranchConfig) {
+    if other.strip_prefixes.is_some() {
+        config.strip_prefixes = other.strip_prefixes;
+    }
+    if other.ticket_pattern.is_some() {
+        config.ticket_pattern = other.ticket_pattern;
+    }
+    if other.max_length.is_some() {
+        config.max_length = other.max_length;
     }
 }
 
@@ -347,13 +1151,23 @@ mod tests {
                 SegmentConfig {
                     name: "username".to_string(),
                     color: Some("Blue".to_string()),
+                    separator: None,
+                    enabled: None,
+                    command: None,
+                    env: None,
                 },
                 SegmentConfig {
                     name: "hostname".to_string(),
                     color: Some("Green".to_string()),
+                    separator: None,
+                    enabled: None,
+                    command: None,
+                    env: None,
                 },
             ],
             mode: None,
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         assert!(config.validate().is_ok());
@@ -365,8 +1179,14 @@ mod tests {
             segments: vec![SegmentConfig {
                 name: "username".to_string(),
                 color: Some("InvalidColor".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: None,
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         assert!(config.validate().is_err());
@@ -378,21 +1198,280 @@ mod tests {
             segments: vec![SegmentConfig {
                 name: "invalid_segment".to_string(),
                 color: Some("Blue".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: None,
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_validate_duplicate_segment() {
+        let mut config = Config::default();
+        config.segments.push(SegmentConfig {
+            name: "hostname".to_string(),
+            color: None,
+            separator: Some(" ".to_string()),
+            enabled: None,
+            command: None,
+            env: None,
+        });
+
+        let err = config.validate().expect_err("duplicate segment");
+        assert_eq!(err.to_string(), "Duplicate segment: hostname");
+    }
+
+    #[test]
+    fn test_validate_segments_requires_current_directory() {
+        let mut config = Config::default();
+        assert!(config.validate_segments().is_ok());
+
+        config
+            .segments
+            .retain(|segment| segment.name != "current_directory");
+        let err = config.validate_segments().expect_err("missing segment");
+        assert_eq!(
+            err.to_string(),
+            "Missing required segment: current_directory"
+        );
+    }
+
+    #[test]
+    fn test_validate_command_segments() {
+        let with_command = |name: &str, command: CommandConfig| {
+            let mut config = Config::default();
+            config.segments.push(SegmentConfig {
+                name: name.to_string(),
+                color: None,
+                separator: None,
+                enabled: None,
+                command: Some(command),
+                env: None,
+            });
+            config.validate()
+        };
+        let command = CommandConfig {
+            run: "cat ~/.oncall".to_string(),
+            timeout: Some("300ms".to_string()),
+            cache_ttl: Some("5m".to_string()),
+            ..Default::default()
+        };
+
+        assert!(with_command("oncall", command.clone()).is_ok());
+        assert_eq!(
+            with_command("hostname", command.clone())
+                .expect_err("built-in name")
+                .to_string(),
+            "Command segment uses a built-in segment name: hostname"
+        );
+        assert_eq!(
+            with_command("branch", command.clone())
+                .expect_err("template variable name")
+                .to_string(),
+            "Command segment uses a template variable name: branch"
+        );
+        assert!(with_command("", command.clone()).is_err());
+        assert!(
+            with_command(
+                "oncall",
+                CommandConfig {
+                    run: " ".to_string(),
+                    ..command.clone()
+                }
+            )
+            .is_err()
+        );
+        assert!(
+            with_command(
+                "oncall",
+                CommandConfig {
+                    timeout: Some("soon".to_string()),
+                    ..command.clone()
+                }
+            )
+            .is_err()
+        );
+        assert!(
+            with_command(
+                "oncall",
+                CommandConfig {
+                    status_colors: Some(vec![StatusColorRule {
+                        status: 1,
+                        color: Some("Crimson".to_string()),
+                        style: None,
+                    }]),
+                    ..command
+                }
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_env_segments() {
+        let with_env = |name: &str, env: EnvConfig| {
+            let mut config = Config::default();
+            config.segments.push(SegmentConfig {
+                name: name.to_string(),
+                color: None,
+                separator: None,
+                enabled: None,
+                command: None,
+                env: Some(env),
+            });
+            config.validate()
+        };
+        let env = EnvConfig {
+            var: "DEPLOY_ENV".to_string(),
+            format: Some("env:{value}".to_string()),
+            values: Some(vec![EnvValueRule {
+                value: "prod".to_string(),
+                text: Some("PROD".to_string()),
+                color: Some("Red".to_string()),
+                style: Some("bold".to_string()),
+            }]),
+            hide: Some("unset".to_string()),
+        };
+
+        assert!(with_env("deploy", env.clone()).is_ok());
+        assert_eq!(
+            with_env("username", env.clone())
+                .expect_err("built-in name")
+                .to_string(),
+            "Env segment uses a built-in segment name: username"
+        );
+        assert_eq!(
+            with_env("exit", env.clone())
+                .expect_err("template variable name")
+                .to_string(),
+            "Env segment uses a template variable name: exit"
+        );
+        for var in ["", "1PASSWORD", "DEPLOY-ENV", "A=B"] {
+            let env = EnvConfig {
+                var: var.to_string(),
+                ..env.clone()
+            };
+            assert!(with_env("deploy", env).is_err(), "{}", var);
+        }
+        let hide = EnvConfig {
+            hide: Some("sometimes".to_string()),
+            ..env.clone()
+        };
+        assert!(with_env("deploy", hide).is_err());
+        let color = EnvConfig {
+            values: Some(vec![EnvValueRule {
+                value: "prod".to_string(),
+                text: None,
+                color: Some("Crimson".to_string()),
+                style: None,
+            }]),
+            ..env.clone()
+        };
+        assert!(with_env("deploy", color).is_err());
+
+        let mut config = Config::default();
+        config.segments.push(SegmentConfig {
+            name: "deploy".to_string(),
+            color: None,
+            separator: None,
+            enabled: None,
+            command: Some(CommandConfig {
+                run: "echo prod".to_string(),
+                ..Default::default()
+            }),
+            env: Some(env),
+        });
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_template() {
+        let mut config = Config {
+            template: Some("{user} {dir".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate().expect_err("unclosed").to_string(),
+            "Invalid template at column 8: unclosed {"
+        );
+
+        config.template = Some("{user}:{dir}\n{deploy} {symbol} ".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config
+                .validate_segments()
+                .expect_err("unknown variable")
+                .to_string(),
+            "Invalid template at line 2, column 2: unknown variable deploy"
+        );
+        config.segments.push(SegmentConfig {
+            name: "deploy".to_string(),
+            color: None,
+            separator: None,
+            enabled: None,
+            command: None,
+            env: Some(EnvConfig {
+                var: "DEPLOY_ENV".to_string(),
+                ..Default::default()
+            }),
+        });
+        assert!(config.validate_segments().is_ok());
+
+        let mut other = ConfigBuilder::new().config;
+        merge_configs(&mut config, other.clone());
+        assert_eq!(
+            config.template.as_deref(),
+            Some("{user}:{dir}\n{deploy} {symbol} ")
+        );
+        other.template = Some("{line} {symbol} ".to_string());
+        merge_configs(&mut config, other);
+        assert_eq!(config.template.as_deref(), Some("{line} {symbol} "));
+    }
+
+    #[test]
+    fn test_env_config_defaults() {
+        let env = EnvConfig::default();
+        assert_eq!(env.format(), "{value}");
+        assert_eq!(env.hide(), EnvHide::Empty);
+    }
+
+    #[test]
+    fn test_command_config_defaults() {
+        let command = CommandConfig::default();
+        assert_eq!(command.timeout(), DEFAULT_COMMAND_TIMEOUT);
+        assert_eq!(command.cache_ttl(), Duration::ZERO);
+        assert!(command.per_directory());
+    }
+
+    #[test]
+    fn test_segment_kind_names() {
+        for segment in Config::default().segments {
+            let kind: SegmentKind = segment.name.parse().expect("segment kind");
+            assert_eq!(kind.as_str(), segment.name);
+        }
+        assert!("prompt_symbol".parse::<SegmentKind>().is_err());
+    }
+
     #[test]
     fn test_validate_invalid_mode() {
         let config = Config {
             segments: vec![SegmentConfig {
                 name: "username".to_string(),
                 color: Some("Blue".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: Some("SingleLine".to_string()),
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         assert!(config.validate().is_err());
@@ -404,16 +1483,28 @@ mod tests {
             segments: vec![SegmentConfig {
                 name: "username".to_string(),
                 color: Some("Blue".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: None,
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         let other = Config {
             segments: vec![SegmentConfig {
                 name: "username".to_string(),
                 color: Some("Red".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: None,
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         merge_configs(&mut base, other);
@@ -427,16 +1518,28 @@ mod tests {
             segments: vec![SegmentConfig {
                 name: "username".to_string(),
                 color: Some("Blue".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: None,
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         let other = Config {
             segments: vec![SegmentConfig {
                 name: "hostname".to_string(),
                 color: Some("Green".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: None,
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         merge_configs(&mut base, other);
@@ -451,16 +1554,28 @@ mod tests {
             segments: vec![SegmentConfig {
                 name: "username".to_string(),
                 color: Some("Blue".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: Some("DualLine".to_string()),
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         let other = Config {
             segments: vec![SegmentConfig {
                 name: "username".to_string(),
                 color: Some("Blue".to_string()),
+                separator: None,
+                enabled: None,
+                command: None,
+                env: None,
             }],
             mode: Some("Inline".to_string()),
+            template: None,
+            git: GitConfig::default(),
             segment_colors: HashMap::new(),
         };
         merge_configs(&mut base, other);
@@ -468,6 +1583,414 @@ mod tests {
         assert_eq!(base.mode.as_deref(), Some("Inline"));
     }
 
+    fn segment_names(segments: &[SegmentConfig]) -> Vec<&str> {
+        segments
+            .iter()
+            .map(|segment| segment.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_segments_order_and_removal() {
+        let segment = |name: &str, enabled| SegmentConfig {
+            name: name.to_string(),
+            color: None,
+            separator: None,
+            enabled,
+            command: None,
+            env: None,
+        };
+        let merged = |other: &[(&str, Option<bool>)]| {
+            let mut segments = ["username", "git_branch", "current_directory"]
+                .map(|name| segment(name, None))
+                .to_vec();
+            let other = other
+                .iter()
+                .map(|(name, enabled)| segment(name, *enabled))
+                .collect();
+            merge_segments(&mut segments, other);
+            segment_names(&segments)
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        // Listing a single segment keeps the order.
+        assert_eq!(
+            merged(&[("current_directory", None)]),
+            ["username", "git_branch", "current_directory"]
+        );
+        // Listed segments swap into the positions they occupy.
+        assert_eq!(
+            merged(&[("current_directory", None), ("username", None)]),
+            ["current_directory", "git_branch", "username"]
+        );
+        // New segments follow the segment listed before them.
+        assert_eq!(
+            merged(&[("username", None), ("hostname", None)]),
+            ["username", "hostname", "git_branch", "current_directory"]
+        );
+        assert_eq!(
+            merged(&[("hostname", None), ("git_branch", None)]),
+            ["username", "hostname", "git_branch", "current_directory"]
+        );
+        assert_eq!(
+            merged(&[("hostname", None)]),
+            ["username", "git_branch", "current_directory", "hostname"]
+        );
+        // Disabled segments are removed, and never added.
+        assert_eq!(
+            merged(&[("git_branch", Some(false)), ("hostname", Some(false))]),
+            ["username", "current_directory"]
+        );
+    }
+
+    #[test]
+    fn test_builder_merges_segments_default_system_user() -> Result<()> {
+        let write_config = |content: &str| -> Result<NamedTempFile> {
+            let mut file = NamedTempFile::new()?;
+            std::io::Write::write_all(&mut file, content.as_bytes())?;
+            Ok(file)
+        };
+        let system = write_config(
+            "segments:\n  - name: hostname\n    enabled: false\n  - name: username\n    color: Red\n",
+        )?;
+        let build = |user: &NamedTempFile| {
+            ConfigBuilder::new()
+                .with_defaults()
+                .merge_path(system.path())?
+                .merge_path(user.path())?
+                .build()
+        };
+
+        let reordered = build(&write_config(
+            "segments:\n  - name: current_directory\n  - name: git_branch\n",
+        )?)?;
+        assert_eq!(
+            segment_names(&reordered.segments),
+            ["username", "current_directory", "git_branch"]
+        );
+        assert_eq!(reordered.get_color("username"), Clrs::Red);
+
+        let restored = build(&write_config(
+            "segments:\n  - name: username\n  - name: hostname\n    color: Olive\n",
+        )?)?;
+        assert_eq!(
+            segment_names(&restored.segments),
+            ["username", "hostname", "git_branch", "current_directory"]
+        );
+        assert_eq!(restored.get_color("hostname"), Clrs::Olive);
+
+        let without_dir = build(&write_config(
+            "segments:\n  - name: current_directory\n    enabled: false\n",
+        )?);
+        assert!(without_dir.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_merges_old_example_config() -> Result<()> {
+        let write_config = |content: &str| -> Result<NamedTempFile> {
+            let mut file = NamedTempFile::new()?;
+            std::io::Write::write_all(&mut file, content.as_bytes())?;
+            Ok(file)
+        };
+        let build = |user: &NamedTempFile| {
+            ConfigBuilder::new()
+                .with_defaults()
+                .merge_path(user.path())?
+                .build()
+        };
+
+        // `config/example.yaml` before the segments list set the order.
+        let old_example = build(&write_config(concat!(
+            "mode: DualLine\n",
+            "segments:\n",
+            "  - name: username\n",
+            "    color: Blue\n",
+            "  - name: hostname\n",
+            "    color: Green\n",
+            "  - name: current_directory\n",
+            "    color: Silver\n",
+            "  - name: git_branch\n",
+            "    color: Red\n",
+        ))?)?;
+        assert_eq!(
+            segment_names(&old_example.segments),
+            ["username", "hostname", "current_directory", "git_branch"]
+        );
+        assert_eq!(old_example.get_color("git_branch"), Clrs::Red);
+
+        let example = build(&write_config(include_str!("../config/example.yaml"))?)?;
+        assert_eq!(
+            segment_names(&example.segments),
+            ["username", "hostname", "git_branch", "current_directory"]
+        );
+        assert_eq!(example.get_color("current_directory"), Clrs::Silver);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_invalid_detached_head_strategy() {
+        let mut config = Config::default();
+        config.git.detached_head = Some(vec!["nearest_branch".to_string()]);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_detached_head_strategies_default_and_configured() {
+        let mut git = GitConfig::default();
+        assert_eq!(
+            git.detached_head_strategies(),
+            DetachedHeadStrategy::DEFAULT_ORDER.to_vec()
+        );
+
+        git.detached_head = Some(vec!["remote_branch".to_string(), "exact_tag".to_string()]);
+        assert_eq!(
+            git.detached_head_strategies(),
+            vec![
+                DetachedHeadStrategy::RemoteBranch,
+                DetachedHeadStrategy::ExactTag
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repo_name_strategies_validate_and_parse() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.git.repo_name_strategies(),
+            vec![RepoNameStrategy::Directory]
+        );
+
+        config.git.repo_name = Some(vec![
+            "workspace_member".to_string(),
+            "remote_url".to_string(),
+        ]);
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.git.repo_name_strategies(),
+            vec![
+                RepoNameStrategy::WorkspaceMember,
+                RepoNameStrategy::RemoteUrl
+            ]
+        );
+
+        config.git.repo_name = Some(vec!["package".to_string()]);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_identity_rules() {
+        let mut config = Config::default();
+        let rule = IdentityRule {
+            path: None,
+            remote: Some("github.com/ourcorp/*".to_string()),
+            email: "@ourcorp.com".to_string(),
+        };
+        config.git.identity = Some(vec![rule.clone()]);
+        assert!(config.validate().is_ok());
+
+        config.git.identity = Some(vec![IdentityRule {
+            remote: None,
+            ..rule.clone()
+        }]);
+        assert!(config.validate().is_err());
+
+        config.git.identity = Some(vec![IdentityRule {
+            path: Some("~/work/[".to_string()),
+            ..rule
+        }]);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_branch_config() {
+        let mut config = Config::default();
+        config.git.branch.ticket_pattern = Some("[A-Z]+-[0-9]+".to_string());
+        config.git.branch.max_length = Some(20);
+        assert!(config.validate().is_ok());
+
+        config.git.branch.ticket_pattern = Some("([A-Z]+".to_string());
+        assert!(config.validate().is_err());
+
+        config.git.branch.ticket_pattern = None;
+        config.git.branch.max_length = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_merge_branch_config_field_by_field() {
+        let mut base = Config::default();
+        base.git.branch.strip_prefixes = Some(vec!["feature/".to_string()]);
+        base.git.branch.max_length = Some(30);
+
+        let mut other = ConfigBuilder::new().config;
+        other.git.branch.max_length = Some(20);
+        merge_configs(&mut base, other);
+
+        assert_eq!(
+            base.git.branch.strip_prefixes,
+            Some(vec!["feature/".to_string()])
+        );
+        assert_eq!(base.git.branch.max_length, Some(20));
+    }
+
+    #[test]
+    fn test_validate_branch_colors() {
+        let mut config = Config::default();
+        let rule = BranchColorRule {
+            pattern: "release/*".to_string(),
+            color: Some("Red".to_string()),
+            style: Some("bold".to_string()),
+        };
+        config.git.branch_colors = Some(vec![rule.clone()]);
+        assert!(config.validate().is_ok());
+
+        config.git.branch_colors = Some(vec![BranchColorRule {
+            color: Some("Crimson".to_string()),
+            ..rule.clone()
+        }]);
+        assert!(config.validate().is_err());
+
+        config.git.branch_colors = Some(vec![BranchColorRule {
+            style: Some("blink".to_string()),
+            ..rule
+        }]);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").ok(), Some(Duration::from_secs(90)));
+        assert_eq!(
+            parse_duration("12h").ok(),
+            Some(Duration::from_secs(12 * 3600))
+        );
+        assert_eq!(
+            parse_duration("1w").ok(),
+            Some(Duration::from_secs(7 * 86400))
+        );
+        assert_eq!(
+            parse_duration("150ms").ok(),
+            Some(Duration::from_millis(150))
+        );
+        assert!(parse_duration("12").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
+
+    #[test]
+    fn test_stale_fetch_after_default_and_validation() {
+        let mut config = Config::default();
+        assert_eq!(config.git.stale_fetch_after(), DEFAULT_STALE_FETCH_AFTER);
+
+        config.git.stale_fetch_after = Some("2d".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.git.stale_fetch_after(),
+            Duration::from_secs(2 * 86400)
+        );
+
+        config.git.stale_fetch_after = Some("soon".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_time_budget_default_and_validation() {
+        let mut config = Config::default();
+        assert_eq!(config.git.time_budget(), None);
+
+        config.git.time_budget = Some("150ms".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.git.time_budget(), Some(Duration::from_millis(150)));
+
+        config.git.time_budget = Some("fast".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_submodule_limit_default_and_merge() {
+        let mut base = Config::default();
+        assert_eq!(base.git.submodule_limit(), DEFAULT_SUBMODULE_LIMIT);
+
+        let mut other = ConfigBuilder::new().config;
+        other.git.submodule_limit = Some(0);
+        merge_configs(&mut base, other);
+        assert_eq!(base.git.submodule_limit(), 0);
+    }
+
+    #[test]
+    fn test_discovery_config_validate_and_merge() {
+        let mut base = Config::default();
+        assert_eq!(
+            base.git.discovery.slow_filesystems(),
+            SlowFilesystemMode::Branch
+        );
+
+        base.git.discovery.slow_filesystems = Some("sometimes".to_string());
+        assert!(base.validate().is_err());
+        base.git.discovery.slow_filesystems = Some("skip".to_string());
+        base.git.discovery.exclude = Some(vec!["/net/[".to_string()]);
+        assert!(base.validate().is_err());
+
+        let mut other = ConfigBuilder::new().config;
+        other.git.discovery.exclude = Some(vec!["/net/*".to_string()]);
+        merge_configs(&mut base, other);
+        assert_eq!(base.git.discovery.exclude(), ["/net/*"]);
+        assert_eq!(
+            base.git.discovery.slow_filesystems(),
+            SlowFilesystemMode::Skip
+        );
+    }
+
+    #[test]
+    fn test_safe_mode_default_and_merge() {
+        let mut base = Config::default();
+        assert!(base.git.safe_mode());
+
+        let mut other = ConfigBuilder::new().config;
+        other.git.safe_mode = Some(false);
+        merge_configs(&mut base, other);
+        assert!(!base.git.safe_mode());
+        merge_configs(&mut base, ConfigBuilder::new().config);
+        assert!(!base.git.safe_mode());
+    }
+
+    #[test]
+    fn test_merge_keeps_git_options_unless_set() {
+        let mut base = Config::default();
+        base.git.detached_head = Some(vec!["exact_tag".to_string()]);
+
+        merge_configs(&mut base, ConfigBuilder::new().config);
+        assert_eq!(base.git.detached_head, Some(vec!["exact_tag".to_string()]));
+
+        let mut other = ConfigBuilder::new().config;
+        other.git.detached_head = Some(vec!["remote_branch".to_string()]);
+        merge_configs(&mut base, other);
+        assert_eq!(
+            base.git.detached_head,
+            Some(vec!["remote_branch".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_load_from_path_reads_git_options() -> Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        std::io::Write::write_all(
+            &mut temp_file,
+            b"segments: []\ngit:\n  detached_head: [nearest_tag]\n",
+        )?;
+
+        let config = Config::load_from_path(temp_file.path())?;
+
+        assert_eq!(
+            config.git.detached_head_strategies(),
+            vec![DetachedHeadStrategy::NearestTag]
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_builder_builds_cache_for_overrides() -> Result<()> {
         let mut temp_file = NamedTempFile::new()?;
@@ -479,7 +2002,7 @@ mod tests {
         let config = ConfigBuilder::new()
             .with_defaults()
             .merge_path(temp_file.path())?
-            .build();
+            .build()?;
 
         assert_eq!(config.get_color("username"), Clrs::Red);
         Ok(())
@@ -493,7 +2016,7 @@ mod tests {
         let config = ConfigBuilder::new()
             .with_defaults()
             .merge_path_if_exists(&missing_path)?
-            .build();
+            .build()?;
 
         assert_eq!(config.get_color("username"), Clrs::Blue);
         Ok(())
@@ -514,6 +2037,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_load_from_path_reads_separators() -> Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        std::io::Write::write_all(
+            &mut temp_file,
+            b"segments:\n  - name: git_branch\n    separator: \" on \"\n",
+        )?;
+
+        let config = Config::load_from_path(temp_file.path())?;
+
+        let names = config
+            .segments
+            .iter()
+            .map(|segment| segment.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["username", "hostname", "git_branch", "current_directory"]
+        );
+        assert_eq!(config.segments[2].separator.as_deref(), Some(" on "));
+        Ok(())
+    }
+
     #[test]
     fn test_load_from_path_missing_file_returns_error() -> Result<()> {
         let temp_dir = TempDir::new()?;
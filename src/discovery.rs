@@ -0,0 +1,298 @@
+//! Limits on repository discovery.
+//!
+//! Decides where Pulse looks for a repository: directories excluded by
+//! `git.discovery.exclude`, the `GIT_CEILING_DIRECTORIES` search boundaries
+//! and network or FUSE filesystems, detected from `/proc/self/mountinfo`, on
+//! which a full discovery can hang the prompt.
+
+use glob::{MatchOptions, Pattern};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::identity::expand_home;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Network filesystems; every FUSE filesystem (`fuse` and `fuse.*`) is
+/// treated the same way.
+const NETWORK_FILESYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "afs",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "lustre",
+    "gpfs",
+    "davfs",
+];
+
+/// Short hash length used for a detached HEAD.
+const SHORT_HASH_LEN: usize = 7;
+
+/// Whether `dir` or one of its parents matches one of the `exclude` globs.
+pub fn is_excluded(dir: &Path, exclude: &[String]) -> bool {
+    let patterns = exclude
+        .iter()
+        .filter_map(|pattern| Pattern::new(&expand_home(pattern)).ok())
+        .collect::<Vec<_>>();
+    dir.ancestors().any(|dir| {
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches_path_with(dir, MATCH_OPTIONS))
+    })
+}
+
+/// Ceiling directories from `GIT_CEILING_DIRECTORIES`.
+pub fn ceiling_dirs() -> Vec<PathBuf> {
+    std::env::var_os("GIT_CEILING_DIRECTORIES")
+        .map(|value| parse_ceiling_dirs(&value))
+        .unwrap_or_default()
+}
+
+/// Parse a colon-separated list of ceiling directories. Relative entries are
+/// ignored, like git does.
+fn parse_ceiling_dirs(value: &OsStr) -> Vec<PathBuf> {
+    std::env::split_paths(value)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| std::fs::canonicalize(&dir).unwrap_or(dir))
+        .collect()
+}
+
+/// Directories searched for a repository containing `start`: `start` and its
+/// parents, stopping below the nearest ceiling directory.
+///
+/// As in git, a ceiling directory is never searched itself, and one that is
+/// equal to `start` has no effect.
+pub fn search_dirs<'a>(start: &'a Path, ceilings: &[PathBuf]) -> impl Iterator<Item = &'a Path> {
+    let limit = ceilings
+        .iter()
+        .filter(|ceiling| start != ceiling.as_path() && start.starts_with(ceiling))
+        .map(|ceiling| ceiling.components().count())
+        .max();
+    start
+        .ancestors()
+        .take_while(move |dir| limit.is_none_or(|limit| dir.components().count() > limit))
+}
+
+/// Type of the filesystem `path` is on, e.g. `ext4` or `nfs4`, or `None`
+/// where `/proc/self/mountinfo` is not available.
+pub fn filesystem_type(path: &Path) -> Option<String> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    mount_filesystem_type(&mountinfo, path)
+}
+
+/// Whether `fs_type` is a network or FUSE filesystem.
+pub fn is_slow_filesystem(fs_type: &str) -> bool {
+    NETWORK_FILESYSTEMS.contains(&fs_type) || fs_type == "fuse" || fs_type.starts_with("fuse.")
+}
+
+/// Find the filesystem type of the mount containing `path` in the contents
+/// of a `mountinfo` file. The deepest mount point wins, and of mounts on the
+/// same mount point the last one, which hides the others.
+fn mount_filesystem_type(mountinfo: &str, path: &Path) -> Option<String> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            // `<id> <parent> <dev> <root> <mount point> <options> [<tags>...] - <type> ...`
+            let (mount, fs) = line.split_once(" - ")?;
+            let mount_point = unescape_mount_point(mount.split(' ').nth(4)?);
+            let fs_type = fs.split(' ').next()?;
+            path.starts_with(&mount_point)
+                .then(|| (mount_point.components().count(), fs_type))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, fs_type)| fs_type.to_string())
+}
+
+/// Decode the octal escapes (`\040` for a space) of a mount point.
+fn unescape_mount_point(escaped: &str) -> PathBuf {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let code = tail
+            .get(..3)
+            .filter(|_| byte == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                bytes.push(code);
+                rest = &tail[3..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// A git repository read from its `HEAD` file only, for filesystems on which
+/// a full discovery is too slow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHead {
+    root: PathBuf,
+    branch: String,
+}
+
+impl GitHead {
+    /// Find the repository containing `start` by walking up to the nearest
+    /// directory with a `.git` directory or file, and read its `HEAD`.
+    pub fn discover(start: &Path, ceilings: &[PathBuf]) -> Option<Self> {
+        search_dirs(start, ceilings).find_map(|dir| {
+            let git_dir = resolve_git_dir(&dir.join(".git"))?;
+            Some(Self {
+                root: dir.to_path_buf(),
+                branch: read_head(&git_dir)?,
+            })
+        })
+    }
+
+    /// Root directory of the work tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Checked-out branch, or the short hash of a detached HEAD.
+    pub fn branch(&self) -> &str {
+        &self.branch
+    }
+}
+
+/// The git directory of a `.git` directory, or of a `.git` file pointing at
+/// it with `gitdir: <path>` (linked worktrees and submodules).
+fn resolve_git_dir(dot_git: &Path) -> Option<PathBuf> {
+    if dot_git.is_dir() {
+        return Some(dot_git.to_path_buf());
+    }
+    let content = std::fs::read_to_string(dot_git).ok()?;
+    let git_dir = content.trim().strip_prefix("gitdir:")?.trim();
+    Some(dot_git.parent()?.join(git_dir))
+}
+
+fn read_head(git_dir: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(
+                reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(reference)
+                    .to_string(),
+            )
+        }
+        None => head.get(..SHORT_HASH_LEN).map(str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 0:32 / /home rw,relatime shared:2 - nfs4 server:/home rw,vers=4.2
+36 35 8:2 / /home/dev/local rw,relatime shared:3 - ext4 /dev/sda2 rw
+37 22 0:45 / /mnt/artifact\\040store rw,nosuid - fuse.sshfs store: rw
+38 22 0:46 / /tmp rw - ext4 /dev/sda3 rw
+39 22 0:47 / /tmp rw - tmpfs tmpfs rw
+";
+
+    #[test]
+    fn test_mount_filesystem_type() {
+        let fs_type = |path: &str| mount_filesystem_type(MOUNTINFO, Path::new(path));
+
+        assert_eq!(fs_type("/usr/bin").as_deref(), Some("ext4"));
+        assert_eq!(fs_type("/home/dev/src").as_deref(), Some("nfs4"));
+        assert_eq!(fs_type("/home/dev/local/src").as_deref(), Some("ext4"));
+        assert_eq!(fs_type("/home/dev/localdir").as_deref(), Some("nfs4"));
+        assert_eq!(
+            fs_type("/mnt/artifact store/a").as_deref(),
+            Some("fuse.sshfs")
+        );
+        assert_eq!(fs_type("/tmp/build").as_deref(), Some("tmpfs"));
+        assert_eq!(mount_filesystem_type("", Path::new("/")), None);
+    }
+
+    #[test]
+    fn test_is_slow_filesystem() {
+        for fs_type in ["nfs", "nfs4", "cifs", "9p", "fuse", "fuse.sshfs"] {
+            assert!(is_slow_filesystem(fs_type), "{}", fs_type);
+        }
+        for fs_type in ["ext4", "btrfs", "tmpfs", "fuseblk", "overlay"] {
+            assert!(!is_slow_filesystem(fs_type), "{}", fs_type);
+        }
+    }
+
+    #[test]
+    fn test_search_dirs_stop_below_ceiling() {
+        let ceilings = [PathBuf::from("/home"), PathBuf::from("/home/dev/src")];
+        let dirs = |start: &'static str| {
+            search_dirs(Path::new(start), &ceilings)
+                .map(|dir| dir.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            dirs("/home/dev/src/pulse/src"),
+            ["/home/dev/src/pulse/src", "/home/dev/src/pulse"]
+        );
+        assert_eq!(dirs("/home/dev/src"), ["/home/dev/src", "/home/dev"]);
+        assert_eq!(dirs("/srv/git"), ["/srv/git", "/srv", "/"]);
+        assert_eq!(
+            parse_ceiling_dirs(OsStr::new("relative:/:")),
+            [PathBuf::from("/")]
+        );
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let exclude = ["/net/*".to_string(), "/srv/**/cache".to_string()];
+
+        assert!(is_excluded(Path::new("/net/host/project/src"), &exclude));
+        assert!(is_excluded(Path::new("/srv/a/b/cache/x"), &exclude));
+        assert!(!is_excluded(Path::new("/net"), &exclude));
+        assert!(!is_excluded(Path::new("/srv/a/b"), &exclude));
+    }
+
+    #[test]
+    fn test_git_head_discovery() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join(".git")).expect("create .git");
+        std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/feature/x\n").expect("write HEAD");
+        let nested = root.join("src");
+        std::fs::create_dir(&nested).expect("create src");
+
+        let head = GitHead::discover(&nested, &[]).expect("git head");
+        assert_eq!(head.root(), root);
+        assert_eq!(head.branch(), "feature/x");
+        assert_eq!(GitHead::discover(&nested, &[root.to_path_buf()]), None);
+
+        let worktree = root.join("worktree");
+        std::fs::create_dir_all(root.join(".git/worktrees/wt")).expect("create worktree dir");
+        std::fs::create_dir(&worktree).expect("create worktree");
+        std::fs::write(worktree.join(".git"), "gitdir: ../.git/worktrees/wt\n")
+            .expect("write .git");
+        std::fs::write(
+            root.join(".git/worktrees/wt/HEAD"),
+            "0123456789abcdef0123456789abcdef01234567\n",
+        )
+        .expect("write HEAD");
+        let head = GitHead::discover(&worktree, &[]).expect("git head");
+        assert_eq!(head.root(), worktree);
+        assert_eq!(head.branch(), "0123456");
+    }
+}
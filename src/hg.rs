@@ -0,0 +1,170 @@
+//! Mercurial repository support.
+//!
+//! Reads the state of a Mercurial working copy directly from its `.hg`
+//! directory, without running `hg`.
+
+use std::path::{Path, PathBuf};
+
+use crate::discovery::search_dirs;
+
+/// Name of the branch a Mercurial working copy is on when `.hg/branch` is
+/// missing.
+const DEFAULT_BRANCH: &str = "default";
+
+/// A Mercurial working copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HgRepository {
+    root: PathBuf,
+}
+
+impl HgRepository {
+    /// Find the working copy containing `start` by walking up to the nearest
+    /// directory with a `.hg` directory, stopping below the nearest ceiling
+    /// directory.
+    pub fn discover(start: &Path, ceilings: &[PathBuf]) -> Option<Self> {
+        search_dirs(start, ceilings)
+            .find(|dir| dir.join(".hg").is_dir())
+            .map(|root| Self {
+                root: root.to_path_buf(),
+            })
+    }
+
+    /// Root directory of the working copy.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The `.hg` directory.
+    pub fn dot_hg(&self) -> PathBuf {
+        self.root.join(".hg")
+    }
+
+    /// Named branch of the working copy, from `.hg/branch`.
+    pub fn branch(&self) -> String {
+        read_trimmed(&self.dot_hg().join("branch")).unwrap_or_else(|| DEFAULT_BRANCH.to_string())
+    }
+
+    /// Active bookmark, from `.hg/bookmarks.current`.
+    pub fn active_bookmark(&self) -> Option<String> {
+        read_trimmed(&self.dot_hg().join("bookmarks.current"))
+    }
+
+    /// Branch text for the prompt: the active bookmark replaces the
+    /// `default` branch and is appended to any other branch, e.g.
+    /// `stable:fix-1234`.
+    pub fn branch_label(&self) -> String {
+        let branch = self.branch();
+        match self.active_bookmark() {
+            Some(bookmark) if branch == DEFAULT_BRANCH => bookmark,
+            Some(bookmark) => format!("{}:{}", branch, bookmark),
+            None => branch,
+        }
+    }
+
+    /// Email of the configured user, from `HGUSER` or `ui.username` in the
+    /// repository's `hgrc`, then the user's `~/.hgrc`.
+    pub fn user_email(&self) -> Option<String> {
+        if let Ok(user) = std::env::var("HGUSER") {
+            return email_from_username(&user);
+        }
+        let home_hgrc = dirs::home_dir().map(|home| home.join(".hgrc"));
+        [Some(self.dot_hg().join("hgrc")), home_hgrc]
+            .into_iter()
+            .flatten()
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .find_map(|content| read_ui_username(&content))
+            .and_then(|user| email_from_username(&user))
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let content = content.trim();
+    (!content.is_empty()).then(|| content.to_string())
+}
+
+/// Value of `username` in the `[ui]` section of an `hgrc` file.
+fn read_ui_username(content: &str) -> Option<String> {
+    let mut in_ui = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            in_ui = section.trim() == "ui";
+        } else if in_ui
+            && let Some((key, value)) = line.split_once('=')
+            && key.trim() == "username"
+        {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+/// Email of a Mercurial username such as `Jane Doe <jane@example.com>`.
+fn email_from_username(user: &str) -> Option<String> {
+    let email = match (user.find('<'), user.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &user[start + 1..end],
+        _ => user.trim(),
+    };
+    email.contains('@').then(|| email.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn init_hg_repo() -> (TempDir, HgRepository) {
+        let temp_dir = TempDir::new().expect("temp dir");
+        std::fs::create_dir(temp_dir.path().join(".hg")).expect("create .hg");
+        let repo = HgRepository {
+            root: temp_dir.path().to_path_buf(),
+        };
+        (temp_dir, repo)
+    }
+
+    #[test]
+    fn test_discover_from_subdirectory() {
+        let (temp_dir, repo) = init_hg_repo();
+        let nested = temp_dir.path().join("src/module");
+        std::fs::create_dir_all(&nested).expect("create dirs");
+
+        assert_eq!(HgRepository::discover(&nested, &[]), Some(repo));
+        assert_eq!(HgRepository::discover(Path::new("/"), &[]), None);
+    }
+
+    #[test]
+    fn test_branch_and_bookmark() {
+        let (_temp_dir, repo) = init_hg_repo();
+        assert_eq!(repo.branch_label(), "default");
+
+        std::fs::write(repo.dot_hg().join("bookmarks.current"), "fix-1234")
+            .expect("write bookmark");
+        assert_eq!(repo.active_bookmark().as_deref(), Some("fix-1234"));
+        assert_eq!(repo.branch_label(), "fix-1234");
+
+        std::fs::write(repo.dot_hg().join("branch"), "stable\n").expect("write branch");
+        assert_eq!(repo.branch_label(), "stable:fix-1234");
+    }
+
+    #[test]
+    fn test_read_ui_username() {
+        let content =
+            "[paths]\nusername = nope\n\n[ui]\n# comment\nusername = Jane Doe <jane@example.com>\n";
+        let user = read_ui_username(content).expect("username");
+
+        assert_eq!(user, "Jane Doe <jane@example.com>");
+        assert_eq!(
+            email_from_username(&user).as_deref(),
+            Some("jane@example.com")
+        );
+        assert_eq!(
+            email_from_username("jane@example.com").as_deref(),
+            Some("jane@example.com")
+        );
+        assert_eq!(email_from_username("Jane Doe"), None);
+    }
+}
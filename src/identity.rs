@@ -0,0 +1,176 @@
+//! Git identity guard.
+//!
+//! Checks `user.email` against the identity expected for a repository, based
+//! on its work tree path and remote URLs.
+
+use glob::{MatchOptions, Pattern};
+use std::path::Path;
+
+use crate::config::IdentityRule;
+
+/// Result of checking `user.email` against the configured identity rules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdentityStatus {
+    /// No rule applies, or `user.email` matches the expected identity.
+    #[default]
+    Ok,
+    /// `user.email` does not match the identity expected for the repository.
+    Mismatch,
+    /// `user.email` is not set.
+    Missing,
+    /// The repository is owned by another user, so its `user.email` is not
+    /// read.
+    Untrusted,
+}
+
+/// Check `email` against the first rule that applies to the repository.
+///
+/// `remote_urls` are normalized as `host/path` without a `.git` suffix,
+/// e.g. `github.com/ourcorp/pulse`.
+pub fn check_identity(
+    email: Option<&str>,
+    work_dir: &Path,
+    remote_urls: &[String],
+    rules: &[IdentityRule],
+) -> IdentityStatus {
+    let Some(email) = email else {
+        return IdentityStatus::Missing;
+    };
+    let rule = rules
+        .iter()
+        .find(|rule| rule_applies(rule, work_dir, remote_urls));
+    match rule {
+        Some(rule) if !email_matches(email, &rule.email) => IdentityStatus::Mismatch,
+        _ => IdentityStatus::Ok,
+    }
+}
+
+/// Normalize a remote URL's host and path to `host/path`, e.g.
+/// `github.com/ourcorp/pulse`.
+pub fn normalize_remote_url(host: Option<&str>, path: &str) -> String {
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    match host {
+        Some(host) => format!("{}/{}", host, path),
+        None => path.to_string(),
+    }
+}
+
+/// A rule applies when all of its patterns match.
+fn rule_applies(rule: &IdentityRule, work_dir: &Path, remote_urls: &[String]) -> bool {
+    let path_matches = rule.path.as_deref().is_none_or(|pattern| {
+        let pattern = expand_home(pattern);
+        glob_matches(&pattern, &work_dir.to_string_lossy())
+    });
+    let remote_matches = rule
+        .remote
+        .as_deref()
+        .is_none_or(|pattern| remote_urls.iter().any(|url| glob_matches(pattern, url)));
+    path_matches && remote_matches
+}
+
+/// Match an exact address, or a domain when `expected` starts with `@`.
+fn email_matches(email: &str, expected: &str) -> bool {
+    let email = email.to_lowercase();
+    let expected = expected.to_lowercase();
+    if expected.starts_with('@') {
+        email.ends_with(&expected)
+    } else {
+        email == expected
+    }
+}
+
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_with(text, options))
+}
+
+/// Expand a leading `~/` to the home directory.
+pub fn expand_home(pattern: &str) -> String {
+    if let Some(rest) = pattern.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(rest).to_string_lossy().to_string();
+    }
+    pattern.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(path: Option<&str>, remote: Option<&str>, email: &str) -> IdentityRule {
+        IdentityRule {
+            path: path.map(str::to_string),
+            remote: remote.map(str::to_string),
+            email: email.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_missing_email() {
+        let status = check_identity(None, Path::new("/src/pulse"), &[], &[]);
+        assert_eq!(status, IdentityStatus::Missing);
+    }
+
+    #[test]
+    fn test_remote_rule_with_domain() {
+        let rules = [rule(None, Some("github.com/ourcorp/*"), "@ourcorp.com")];
+        let remotes = ["github.com/ourcorp/pulse".to_string()];
+        let work_dir = Path::new("/src/pulse");
+
+        assert_eq!(
+            check_identity(Some("Me@OurCorp.com"), work_dir, &remotes, &rules),
+            IdentityStatus::Ok
+        );
+        assert_eq!(
+            check_identity(Some("me@example.com"), work_dir, &remotes, &rules),
+            IdentityStatus::Mismatch
+        );
+
+        let other = ["github.com/someone/pulse".to_string()];
+        assert_eq!(
+            check_identity(Some("me@example.com"), work_dir, &other, &rules),
+            IdentityStatus::Ok
+        );
+    }
+
+    #[test]
+    fn test_first_applicable_path_rule_wins() {
+        let rules = [
+            rule(Some("/work/oss/**"), None, "me@example.com"),
+            rule(Some("/work/**"), None, "me@ourcorp.com"),
+        ];
+
+        let oss = Path::new("/work/oss/pulse");
+        assert_eq!(
+            check_identity(Some("me@example.com"), oss, &[], &rules),
+            IdentityStatus::Ok
+        );
+
+        let corp = Path::new("/work/api");
+        assert_eq!(
+            check_identity(Some("me@example.com"), corp, &[], &rules),
+            IdentityStatus::Mismatch
+        );
+    }
+
+    #[test]
+    fn test_normalize_remote_url() {
+        assert_eq!(
+            normalize_remote_url(Some("github.com"), "/ourcorp/pulse.git"),
+            "github.com/ourcorp/pulse"
+        );
+        assert_eq!(
+            normalize_remote_url(Some("github.com"), "ourcorp/pulse.git"),
+            "github.com/ourcorp/pulse"
+        );
+        assert_eq!(
+            normalize_remote_url(None, "/srv/git/pulse.git"),
+            "srv/git/pulse"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Jujutsu (jj) workspace support.
+//!
+//! Workspaces are detected by their `.jj` directory. jj keeps its state in a
+//! binary operation store, so the working-copy commit is read through `jj`
+//! itself, without snapshotting the working copy.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::command::output_with_timeout;
+use crate::discovery::search_dirs;
+
+/// Template printing the change ID prefix, local bookmarks and author email
+/// of the working-copy commit, one per line.
+const WORKING_COPY_TEMPLATE: &str = concat!(
+    r#"change_id.shortest(8) ++ "\n" ++ "#,
+    r#"local_bookmarks.map(|b| b.name()).join(" ") ++ "\n" ++ "#,
+    r#"author.email() ++ "\n""#,
+);
+
+/// A jj workspace, either native or colocated with a git repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JjWorkspace {
+    root: PathBuf,
+}
+
+/// The working-copy commit of a jj workspace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JjWorkingCopy {
+    /// Shortest unique prefix of the change ID, padded to 8 characters.
+    pub change_id: String,
+    /// Local bookmarks pointing at the working-copy commit.
+    pub bookmarks: Vec<String>,
+    /// Author email of the working-copy commit, i.e. jj's `user.email`.
+    pub email: Option<String>,
+}
+
+impl JjWorkingCopy {
+    /// Branch text for the prompt, e.g. `kntqzsqt` or `kntqzsqt main`.
+    pub fn label(&self) -> String {
+        std::iter::once(&self.change_id)
+            .chain(&self.bookmarks)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl JjWorkspace {
+    /// Find the workspace containing `start` by walking up to the nearest
+    /// directory with a `.jj` directory, stopping below the nearest ceiling
+    /// directory.
+    pub fn discover(start: &Path, ceilings: &[PathBuf]) -> Option<Self> {
+        search_dirs(start, ceilings)
+            .find(|dir| dir.join(".jj").is_dir())
+            .map(|root| Self {
+                root: root.to_path_buf(),
+            })
+    }
+
+    /// Root directory of the workspace.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Read the working-copy commit with `jj log`, or `None` if `jj` is not
+    /// installed, fails or does not finish within `timeout`.
+    pub fn working_copy(&self, timeout: Duration) -> Option<JjWorkingCopy> {
+        let mut command = Command::new("jj");
+        command
+            .args(["--ignore-working-copy", "--no-pager", "--color", "never"])
+            .args(["log", "--no-graph", "-r", "@", "-T", WORKING_COPY_TEMPLATE])
+            .current_dir(&self.root);
+        let (status, stdout) = output_with_timeout(&mut command, timeout)?;
+        if !status.success() {
+            return None;
+        }
+        parse_working_copy(&String::from_utf8_lossy(&stdout))
+    }
+}
+
+/// Parse the output of [`WORKING_COPY_TEMPLATE`].
+fn parse_working_copy(output: &str) -> Option<JjWorkingCopy> {
+    let mut lines = output.lines().map(str::trim);
+    let change_id = lines.next().filter(|id| !id.is_empty())?.to_string();
+    let bookmarks = lines
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    let email = lines
+        .next()
+        .filter(|email| !email.is_empty())
+        .map(str::to_string);
+    Some(JjWorkingCopy {
+        change_id,
+        bookmarks,
+        email,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_discover_from_subdirectory() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        std::fs::create_dir(temp_dir.path().join(".jj")).expect("create .jj");
+        let nested = temp_dir.path().join("src");
+        std::fs::create_dir(&nested).expect("create src");
+
+        let workspace = JjWorkspace::discover(&nested, &[]).expect("workspace");
+        assert_eq!(workspace.root(), temp_dir.path());
+        assert_eq!(JjWorkspace::discover(Path::new("/"), &[]), None);
+    }
+
+    #[test]
+    fn test_parse_working_copy() {
+        let working_copy =
+            parse_working_copy("kntqzsqt\nmain release\ndev@example.com\n").expect("parse");
+        assert_eq!(
+            working_copy,
+            JjWorkingCopy {
+                change_id: "kntqzsqt".to_string(),
+                bookmarks: vec!["main".to_string(), "release".to_string()],
+                email: Some("dev@example.com".to_string()),
+            }
+        );
+        assert_eq!(working_copy.label(), "kntqzsqt main release");
+
+        let anonymous = parse_working_copy("zzxyvwuo\n\n\n").expect("parse");
+        assert_eq!(anonymous.label(), "zzxyvwuo");
+        assert_eq!(anonymous.email, None);
+        assert_eq!(parse_working_copy(""), None);
+    }
+}
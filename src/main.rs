@@ -10,9 +10,17 @@ use log::error;
 
 mod cli;
 mod clrs;
+mod command;
 mod config;
+mod discovery;
+mod hg;
+mod identity;
 mod install;
+mod jj;
 mod prompt;
+mod template;
+mod trust;
+mod workspace;
 
 /// Main entry point for the Pulse application.
 ///
@@ -70,6 +78,7 @@ fn main() -> Result<()> {
     })?;
     if args.inline {
         config.mode = Some("Inline".to_string());
+        config.template = None;
     }
     let prompt = prompt::generate_prompt(&config).map_err(|e| {
         error!("Failed to generate prompt: {}", e);
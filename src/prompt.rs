@@ -4,19 +4,55 @@
 //! Supports different modes and customizable colors.
 
 use std::cell::OnceCell;
+use std::collections::HashMap;
 use std::fmt;
 use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Result, anyhow};
 
 use crate::clrs::Clrs;
-use crate::config::Config;
+use crate::command::{self, SegmentCommand};
+use crate::config::{
+    BranchConfig, CommandConfig, Config, DetachedHeadStrategy, EnvConfig, EnvHide, GitConfig,
+    RepoNameStrategy, SegmentConfig, SegmentKind, SlowFilesystemMode,
+};
+use crate::discovery::{self, GitHead};
+use crate::hg::HgRepository;
+use crate::identity::{IdentityStatus, check_identity, normalize_remote_url};
+use crate::jj::{JjWorkingCopy, JjWorkspace};
+use crate::template::{Template, Value};
+use crate::trust;
+use crate::workspace::find_workspace_member;
 use crossterm::terminal::size;
-use owo_colors::OwoColorize;
+use gix::sec::Trust;
+use owo_colors::{OwoColorize, Style};
 
 const DEFAULT_TERM_WIDTH: usize = 120;
 const TRUNCATION_THRESHOLD: usize = 3;
+/// Shown in the git block when the repository is a linked worktree.
+const WORKTREE_MARKER: &str = "⎇";
+/// Shown in the git block when the last fetch is older than the threshold.
+const STALE_FETCH_MARKER: &str = "⟳";
+/// Shown in the git block for a shallow clone, whose history is cut off.
+const SHALLOW_MARKER: &str = "⊥";
+/// Shown in the git block when only part of the worktree is checked out.
+const SPARSE_CHECKOUT_MARKER: &str = "◌";
+/// Shown in the git block for a partial clone, which fetches objects on demand.
+const PARTIAL_CLONE_MARKER: &str = "◐";
+/// Shown before the path when the current directory is git-ignored or
+/// inside the git directory.
+const IGNORED_DIR_MARKER: &str = "⊘";
+/// Precedes the submodule summary in the git block.
+const SUBMODULE_MARKER: &str = "◫";
+/// Separates the superproject from the submodule in the repository name.
+const SUBMODULE_SEPARATOR: &str = " › ";
+/// Shown in the git block when slow indicators missed the time budget.
+const PARTIAL_MARKER: &str = "⧗";
+/// Timeout of `jj log` when `git.time_budget` is not set.
+const JJ_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ShellKind {
@@ -68,28 +104,131 @@ pub fn get_terminal_width() -> Option<u16> {
     size().ok().map(|(w, _)| w)
 }
 
-/// Discover a git repository starting from the given path.
-///
-/// This allows tests and callers to request discovery from an explicit
-/// directory, while keeping a convenience wrapper for the common
-/// case of discovering from the current working directory.
+/// Discover a git repository starting from the given path, without
+/// discovery limits.
+#[cfg(test)]
 fn discover_git_repo_in<P: AsRef<std::path::Path>>(path: P) -> Option<gix::Repository> {
-    gix::discover(path.as_ref()).ok()
+    discover_git_repo_below(path.as_ref(), &[], true)
+}
+
+/// Discover a git repository containing `path` below the nearest ceiling
+/// directory.
+///
+/// In safe mode, a repository owned by another user is opened without
+/// following config includes, unless `safe.directory` lists it.
+fn discover_git_repo_below(
+    path: &std::path::Path,
+    ceilings: &[PathBuf],
+    safe_mode: bool,
+) -> Option<gix::Repository> {
+    let options = gix::discover::upwards::Options {
+        ceiling_dirs: ceilings.to_vec(),
+        match_ceiling_dir_or_error: false,
+        ..Default::default()
+    };
+    let trust_map = gix::sec::trust::Mapping {
+        full: trust::trusted_options(),
+        reduced: match safe_mode {
+            true => trust::untrusted_options(),
+            false => trust::trusted_options(),
+        },
+    };
+    let repo: gix::Repository = gix::ThreadSafeRepository::discover_opts(path, options, trust_map)
+        .ok()?
+        .into();
+    if safe_mode && repo.git_dir_trust() == Trust::Reduced && trust::is_safe_directory(&repo) {
+        return gix::open_opts(repo.git_dir(), trust::trusted_options()).ok();
+    }
+    Some(repo)
+}
+
+/// Discover the repository containing the given path, honoring
+/// `git.discovery` and `GIT_CEILING_DIRECTORIES`.
+///
+/// Nothing is discovered in excluded directories. On network and FUSE
+/// filesystems, the repository is skipped or only its branch is read,
+/// depending on `git.discovery.slow_filesystems`.
+fn discover_vcs_in<P: AsRef<std::path::Path>>(path: P, git_config: &GitConfig) -> Option<Vcs> {
+    let path = std::fs::canonicalize(path.as_ref()).ok()?;
+    let discovery = &git_config.discovery;
+    if discovery::is_excluded(&path, discovery.exclude()) {
+        return None;
+    }
+    let ceilings = discovery::ceiling_dirs();
+    let mode = discovery.slow_filesystems();
+    if mode != SlowFilesystemMode::Full && is_on_slow_filesystem(&path) {
+        return match mode {
+            SlowFilesystemMode::Branch => GitHead::discover(&path, &ceilings).map(Vcs::GitHead),
+            _ => None,
+        };
+    }
+    discover_nearest_vcs(&path, &ceilings, git_config.safe_mode())
+}
+
+/// Whether `path` is on a network or FUSE filesystem.
+fn is_on_slow_filesystem(path: &std::path::Path) -> bool {
+    discovery::filesystem_type(path).is_some_and(|fs_type| discovery::is_slow_filesystem(&fs_type))
+}
+
+/// Discover the repository containing the canonical `path`.
+///
+/// When repositories are nested, the one whose root is nearest to `path`
+/// wins. A jj workspace colocated with a git repository is preferred over
+/// the git repository.
+fn discover_nearest_vcs(
+    path: &std::path::Path,
+    ceilings: &[PathBuf],
+    safe_mode: bool,
+) -> Option<Vcs> {
+    let git = discover_git_repo_below(path, ceilings, safe_mode).map(|repo| {
+        let root = repo
+            .work_dir()
+            .and_then(|dir| std::fs::canonicalize(dir).ok());
+        (root, repo)
+    });
+    let hg = HgRepository::discover(path, ceilings);
+    let jj = JjWorkspace::discover(path, ceilings);
+
+    // Every root is an ancestor of `path`, so the deepest one is nearest.
+    let depth = |root: Option<&std::path::Path>| root.map_or(0, |root| root.components().count());
+    let git_depth = depth(git.as_ref().and_then(|(root, _)| root.as_deref()));
+    let hg_depth = depth(hg.as_ref().map(HgRepository::root));
+    if let Some(workspace) = jj
+        && depth(Some(workspace.root())) >= git_depth.max(hg_depth)
+    {
+        let git = git
+            .filter(|(root, _)| root.as_deref() == Some(workspace.root()))
+            .map(|(_, repo)| Box::new(repo));
+        return Some(Vcs::Jujutsu { workspace, git });
+    }
+    match git {
+        Some((_, git)) if git_depth >= hg_depth => Some(Vcs::Git(Box::new(git))),
+        _ => hg.map(Vcs::Mercurial),
+    }
 }
 
 /// Convenience wrapper that discovers a repository from the current
-/// working directory (keeps the previous public behaviour).
-fn discover_git_repo() -> Option<gix::Repository> {
-    discover_git_repo_in(".")
+/// working directory.
+fn discover_vcs(git_config: &GitConfig) -> Option<Vcs> {
+    discover_vcs_in(".", git_config)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct PromptColors {
     pub user_color: owo_colors::DynColors,
     pub host_color: owo_colors::DynColors,
     pub git_color: owo_colors::DynColors,
     pub white: owo_colors::DynColors,
     pub dir_color: owo_colors::DynColors,
+    pub warning: owo_colors::DynColors,
+    pub branch_rules: Vec<BranchStyleRule>,
+}
+
+/// A resolved `git.branch_colors` rule.
+#[derive(Debug, Clone)]
+pub struct BranchStyleRule {
+    pub pattern: glob::Pattern,
+    pub style: Style,
 }
 
 impl PromptColors {
@@ -100,21 +239,360 @@ impl PromptColors {
             git_color: config.get_color("git_branch").to_dyn(),
             white: Clrs::White.to_dyn(),
             dir_color: config.get_color("current_directory").to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: branch_style_rules(config),
+        }
+    }
+
+    /// Style of `branch`: the first matching branch color rule, otherwise
+    /// the git color.
+    pub fn branch_style(&self, branch: &str) -> Style {
+        self.branch_rules
+            .iter()
+            .find(|rule| rule.pattern.matches_with(branch, BRANCH_MATCH_OPTIONS))
+            .map_or_else(|| Style::new().color(self.git_color), |rule| rule.style)
+    }
+}
+
+const BRANCH_MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn branch_style_rules(config: &Config) -> Vec<BranchStyleRule> {
+    let git_color = config.get_color("git_branch");
+    config
+        .git
+        .branch_colors
+        .iter()
+        .flatten()
+        .filter_map(|rule| {
+            let pattern = glob::Pattern::new(&rule.pattern).ok()?;
+            let color = rule
+                .color
+                .as_deref()
+                .and_then(|color| color.parse::<Clrs>().ok())
+                .unwrap_or(git_color);
+            let style = text_style(color, rule.style.as_deref());
+            Some(BranchStyleRule { pattern, style })
+        })
+        .collect()
+}
+
+/// Style of `color` with a configured text style such as "bold".
+fn text_style(color: Clrs, style: Option<&str>) -> Style {
+    let base = Style::new().color(color.to_dyn());
+    match style {
+        Some("bold") => base.bold(),
+        Some("dimmed") => base.dimmed(),
+        Some("italic") => base.italic(),
+        Some("underline") => base.underline(),
+        _ => base,
+    }
+}
+
+/// A segment of the prompt line and the separator placed before it.
+#[derive(Debug, Clone)]
+struct LayoutSegment {
+    name: String,
+    kind: SegmentKind,
+    separator: Option<String>,
+    /// The command of a command segment and the styles of its output.
+    command: Option<(SegmentCommand, CommandStyles)>,
+    /// The variable of an env segment and how its value is shown.
+    env: Option<EnvSegment>,
+    /// Output of the command, or value of the variable, once it has been
+    /// read.
+    output: Option<RenderedSegment>,
+}
+
+/// Styles of a command segment's output, by exit status.
+#[derive(Debug, Clone)]
+struct CommandStyles {
+    rules: Vec<(i32, Style)>,
+    default: Style,
+}
+
+impl CommandStyles {
+    fn from_config(segment: &SegmentConfig, command: &CommandConfig, config: &Config) -> Self {
+        let color = config.get_color(&segment.name);
+        let rules = command
+            .status_colors
+            .iter()
+            .flatten()
+            .map(|rule| {
+                let color = rule
+                    .color
+                    .as_deref()
+                    .and_then(|color| color.parse::<Clrs>().ok())
+                    .unwrap_or(color);
+                (rule.status, text_style(color, rule.style.as_deref()))
+            })
+            .collect();
+        Self {
+            rules,
+            default: Style::new().color(color.to_dyn()),
+        }
+    }
+
+    fn style(&self, status: Option<i32>) -> Style {
+        self.rules
+            .iter()
+            .find(|(rule_status, _)| Some(*rule_status) == status)
+            .map_or(self.default, |(_, style)| *style)
+    }
+}
+
+/// Variable, format and value mapping of an env segment.
+#[derive(Debug, Clone)]
+struct EnvSegment {
+    var: String,
+    format: String,
+    hide: EnvHide,
+    /// Display text and style by value.
+    values: Vec<(String, Option<String>, Style)>,
+    default: Style,
+}
+
+impl EnvSegment {
+    fn from_config(segment: &SegmentConfig, env: &EnvConfig, config: &Config) -> Self {
+        let color = config.get_color(&segment.name);
+        let values = env
+            .values
+            .iter()
+            .flatten()
+            .map(|rule| {
+                let color = rule
+                    .color
+                    .as_deref()
+                    .and_then(|color| color.parse::<Clrs>().ok())
+                    .unwrap_or(color);
+                let style = text_style(color, rule.style.as_deref());
+                (rule.value.clone(), rule.text.clone(), style)
+            })
+            .collect();
+        Self {
+            var: env.var.clone(),
+            format: env.format().to_string(),
+            hide: env.hide(),
+            values,
+            default: Style::new().color(color.to_dyn()),
+        }
+    }
+
+    /// Render the variable's `value`, or `None` if the segment is hidden.
+    fn render(&self, value: Option<&str>) -> Option<RenderedSegment> {
+        let hidden = match self.hide {
+            EnvHide::Unset => value.is_none(),
+            EnvHide::Empty => value.is_none_or(str::is_empty),
+            EnvHide::Never => false,
+        };
+        if hidden {
+            return None;
+        }
+        let value = value.unwrap_or_default();
+        let (text, style) = self
+            .values
+            .iter()
+            .find(|(rule_value, _, _)| rule_value == value)
+            .map_or((value, self.default), |(_, text, style)| {
+                (text.as_deref().unwrap_or(value), *style)
+            });
+        let text = self.format.replace("{value}", text);
+        (!text.is_empty()).then(|| RenderedSegment::painted(&text, text.style(style)))
+    }
+}
+
+/// Order and separators of the prompt line segments, from `segments` in the
+/// config.
+#[derive(Debug, Clone)]
+pub struct SegmentLayout {
+    segments: Vec<LayoutSegment>,
+}
+
+impl SegmentLayout {
+    /// Disabled segments and unknown segment names are skipped;
+    /// [`Config::validate`] rejects unknown names when a config file is
+    /// loaded.
+    pub fn from_config(config: &Config) -> Self {
+        let segments = config
+            .segments
+            .iter()
+            .filter(|segment| segment.is_enabled())
+            .filter_map(|segment| {
+                Some(LayoutSegment {
+                    name: segment.name.clone(),
+                    kind: segment.kind().ok()?,
+                    separator: segment.separator.clone(),
+                    command: segment.command.as_ref().map(|command| {
+                        (
+                            SegmentCommand::from_config(command),
+                            CommandStyles::from_config(segment, command, config),
+                        )
+                    }),
+                    env: segment
+                        .env
+                        .as_ref()
+                        .map(|env| EnvSegment::from_config(segment, env, config)),
+                    output: None,
+                })
+            })
+            .collect();
+        Self { segments }
+    }
+
+    /// Run the commands of the command segments in `cwd`, side by side, so
+    /// that they take no longer than the slowest one.
+    fn run_commands(&mut self, cwd: &std::path::Path) {
+        let cache_dir = command::cache_dir();
+        let cache_dir = cache_dir.as_deref();
+        let cache_ttl = self
+            .segments
+            .iter()
+            .filter_map(|segment| Some(segment.command.as_ref()?.0.cache_ttl()))
+            .max();
+        if let (Some(dir), Some(cache_ttl)) = (cache_dir, cache_ttl) {
+            command::prune_cache(dir, cache_ttl);
+        }
+        std::thread::scope(|scope| {
+            for segment in &mut self.segments {
+                let Some((command, styles)) = &segment.command else {
+                    continue;
+                };
+                let output = &mut segment.output;
+                scope.spawn(move || {
+                    *output = command
+                        .output(cwd, cache_dir)
+                        .filter(|output| !output.text.is_empty())
+                        .map(|output| {
+                            let style = styles.style(output.status);
+                            RenderedSegment::painted(&output.text, output.text.style(style))
+                        });
+                });
+            }
+        });
+    }
+
+    /// Read the variables of the env segments with `lookup`.
+    fn read_env(&mut self, lookup: impl Fn(&str) -> Option<String>) {
+        for segment in &mut self.segments {
+            if let Some(env) = &segment.env {
+                segment.output = env.render(lookup(&env.var).as_deref());
+            }
+        }
+    }
+
+    /// Output of the command and env segments that are shown, by segment
+    /// name.
+    fn outputs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.segments.iter().filter_map(|segment| {
+            Some((
+                segment.name.as_str(),
+                segment.output.as_ref()?.text.as_str(),
+            ))
+        })
+    }
+
+    /// Join the segments in layout order. A segment for which `render`
+    /// returns `None` is left out together with its separator.
+    fn render(
+        &self,
+        colors: &PromptColors,
+        mut render: impl FnMut(SegmentKind) -> Option<RenderedSegment>,
+    ) -> RenderedSegment {
+        let mut line = RenderedSegment::default();
+        let mut previous = None;
+        for segment in &self.segments {
+            let rendered = match segment.kind {
+                SegmentKind::Command | SegmentKind::Env => segment.output.clone(),
+                kind => render(kind),
+            };
+            let Some(rendered) = rendered else {
+                continue;
+            };
+            if let Some(previous) = previous {
+                let separator = segment
+                    .separator
+                    .as_deref()
+                    .unwrap_or_else(|| default_separator(previous, segment.kind));
+                match rendered.warning {
+                    true => line.push(separator, separator.color(colors.warning).bold()),
+                    false => line.push(separator, separator.color(colors.white)),
+                }
+            }
+            line.text.push_str(&rendered.text);
+            line.width += rendered.width;
+            previous = Some(segment.kind);
+        }
+        line
+    }
+}
+
+impl Default for SegmentLayout {
+    fn default() -> Self {
+        Self::from_config(&Config::default())
+    }
+}
+
+/// Separator between two segments when none is configured, giving
+/// `user@host:dir` outside a repository and `user@host: [repo : branch] dir`
+/// inside one.
+fn default_separator(previous: SegmentKind, next: SegmentKind) -> &'static str {
+    match (previous, next) {
+        (SegmentKind::Username, SegmentKind::Hostname) => "@",
+        (SegmentKind::Username | SegmentKind::Hostname, SegmentKind::GitBranch) => ": ",
+        (SegmentKind::Username | SegmentKind::Hostname, SegmentKind::CurrentDirectory) => ":",
+        _ => " ",
+    }
+}
+
+/// Colored text of a segment and its width in columns.
+#[derive(Debug, Clone, Default)]
+struct RenderedSegment {
+    text: String,
+    width: usize,
+    /// Paint the separator before the segment as a warning.
+    warning: bool,
+}
+
+impl RenderedSegment {
+    fn painted(text: &str, painted: impl fmt::Display) -> Self {
+        let mut segment = Self::default();
+        segment.push(text, painted);
+        segment
+    }
+
+    /// Append `painted`, which is `text` with colors applied.
+    fn push(&mut self, text: &str, painted: impl fmt::Display) {
+        if !text.is_empty() {
+            self.text.push_str(&painted.to_string());
+            self.width += visual_width(text);
         }
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GitDisplayMode {
+    /// `Full` plus the distance from the default branch.
+    Extended,
     Full,
     Mini,
     Micro,
     Nano,
 }
 
+impl GitDisplayMode {
+    /// Whether the mode shows the branch and every indicator in full.
+    fn is_full(self) -> bool {
+        matches!(self, GitDisplayMode::Extended | GitDisplayMode::Full)
+    }
+}
+
 impl fmt::Display for GitDisplayMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            GitDisplayMode::Extended => write!(f, "Extended"),
             GitDisplayMode::Full => write!(f, "Full"),
             GitDisplayMode::Mini => write!(f, "Mini"),
             GitDisplayMode::Micro => write!(f, "Micro"),
@@ -123,12 +601,132 @@ impl fmt::Display for GitDisplayMode {
     }
 }
 
+/// Working-tree status counters, mirroring the sections of `git status`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GitStatus {
+    pub staged: usize,
+    pub modified: usize,
+    pub deleted: usize,
+    pub renamed: usize,
+    pub untracked: usize,
+    pub conflicted: usize,
+}
+
+impl GitStatus {
+    pub fn is_dirty(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+/// Relationship between the current branch and its configured upstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamStatus {
+    /// The branch has no `branch.<name>.merge` configuration.
+    NoUpstream,
+    /// An upstream is configured, but its tracking ref no longer exists.
+    Gone,
+    /// Commits only on the local branch (`ahead`) or only upstream (`behind`).
+    Tracking { ahead: usize, behind: usize },
+}
+
+/// Submodules of a superproject that need attention.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubmoduleSummary {
+    /// Submodules without a cloned repository or checked-out worktree.
+    pub uninitialized: usize,
+    /// Submodules whose HEAD differs from the commit recorded in the index.
+    pub out_of_sync: usize,
+    /// Submodules with changes in their worktree.
+    pub dirty: usize,
+    /// Whether there are more submodules than were inspected.
+    pub truncated: bool,
+}
+
+/// Commits between HEAD and the repository's default branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultBranchDistance {
+    /// Short name of the default branch, e.g. `main`.
+    pub name: String,
+    /// Commits only on HEAD.
+    pub ahead: usize,
+    /// Commits only on the default branch.
+    pub behind: usize,
+}
+
+/// An operation such as a rebase or merge that stopped midway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitOperation {
+    /// Short label in the style of git's own prompt, e.g. `REBASE-i`.
+    pub label: &'static str,
+    /// Current and total step of a rebase or `git am` session.
+    pub progress: Option<(usize, usize)>,
+    /// Short name of the branch being rebased, if any.
+    pub head_name: Option<String>,
+}
+
+/// Optional indicators rendered inside the git block after the branch, plus
+/// the identity check shown on the email.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitIndicators {
+    pub operation: Option<GitOperation>,
+    pub upstream: Option<UpstreamStatus>,
+    pub default_branch: Option<DefaultBranchDistance>,
+    pub status: Option<GitStatus>,
+    pub stash: usize,
+    pub submodules: SubmoduleSummary,
+    pub linked_worktree: bool,
+    pub shallow: bool,
+    pub sparse_checkout: bool,
+    pub partial_clone: bool,
+    /// The current directory is git-ignored or inside the git directory.
+    pub ignored_dir: bool,
+    pub identity: IdentityStatus,
+    /// Shortened branch shown by the compact display modes instead of `…`.
+    pub compact_branch: Option<String>,
+    pub commit_age: Option<Duration>,
+    pub stale_fetch: bool,
+    /// Slow indicators did not finish within `git.time_budget`.
+    pub partial: bool,
+}
+
+/// A repository of one of the supported version control systems.
+///
+/// Every system is summarized as a [`GitInfo`], so they all share the same
+/// line format and display-mode logic.
+pub enum Vcs {
+    Git(Box<gix::Repository>),
+    /// A git repository on a slow filesystem, of which only `HEAD` is read.
+    GitHead(GitHead),
+    Mercurial(HgRepository),
+    /// A jj workspace, with the git repository it is colocated with.
+    Jujutsu {
+        workspace: JjWorkspace,
+        git: Option<Box<gix::Repository>>,
+    },
+}
+
+impl Vcs {
+    fn build_info(&self, git_config: &GitConfig) -> Option<GitInfo> {
+        match self {
+            Vcs::Git(repo) => build_git_info(repo, git_config),
+            Vcs::GitHead(head) => build_git_head_info(head),
+            Vcs::Mercurial(repo) => build_hg_info(repo, git_config),
+            Vcs::Jujutsu { workspace, git } => build_jj_info(workspace, git.as_deref(), git_config),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GitInfo {
     pub repo_name: String,
     pub branch: String,
     pub user_email: Option<String>,
     pub work_dir: PathBuf,
+    pub indicators: GitIndicators,
+    pub head_commit_time: Option<SystemTime>,
+    pub last_fetch_time: Option<SystemTime>,
+    /// Color of the git block, overriding the `git_branch` color.
+    pub color: Option<Clrs>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -145,18 +743,24 @@ impl PromptLayout {
         }
     }
 
-    fn as_str(self) -> &'static str {
-        match self {
-            PromptLayout::Inline => "Inline",
-            PromptLayout::DualLine => "DualLine",
-        }
+    /// Template of the layout: the prompt line and the prompt symbol, on a
+    /// line of their own after the exit code in the dual-line layout.
+    fn template(self) -> Template {
+        let template = match self {
+            PromptLayout::Inline => "{line} {symbol} ",
+            PromptLayout::DualLine => "{line}\n└─ {exit} {symbol} ",
+        };
+        Template::parse(template).unwrap_or_default()
     }
 }
 
 #[derive(Debug, Clone)]
 struct PromptBuilderData {
-    mode: PromptLayout,
+    template: Template,
     colors: PromptColors,
+    layout: SegmentLayout,
+    branch: BranchConfig,
+    stale_fetch_after: Duration,
     user: Option<String>,
     host: Option<String>,
     dir: Option<String>,
@@ -186,11 +790,22 @@ pub struct PromptBuilder<State> {
 }
 
 impl PromptBuilder<NeedsUser> {
+    /// An invalid template falls back to the template of the display mode;
+    /// [`Config::validate`] rejects it when a config file is loaded.
     pub fn from_config(config: &Config) -> Self {
+        let mode = PromptLayout::from_config(config.mode.as_deref());
+        let template = config
+            .template
+            .as_deref()
+            .and_then(|template| Template::parse(template).ok())
+            .unwrap_or_else(|| mode.template());
         Self {
             data: PromptBuilderData {
-                mode: PromptLayout::from_config(config.mode.as_deref()),
+                template,
                 colors: PromptColors::from_config(config),
+                layout: SegmentLayout::from_config(config),
+                branch: config.git.branch.clone(),
+                stale_fetch_after: config.git.stale_fetch_after(),
                 user: None,
                 host: None,
                 dir: None,
@@ -248,7 +863,37 @@ impl PromptBuilder<Ready> {
             .dir
             .ok_or_else(|| anyhow!("PromptBuilder missing dir"))?;
 
-        let first_line = if let Some(info) = self.data.git_info {
+        // Command and env segments are read once, before the display mode is
+        // chosen.
+        let mut layout = self.data.layout;
+        layout.read_env(|var| std::env::var(var).ok());
+        let cwd = self
+            .data
+            .current_dir
+            .clone()
+            .or_else(|| std::env::current_dir().ok());
+        if let Some(cwd) = cwd {
+            layout.run_commands(&cwd);
+        }
+
+        let text = |text: &str, color: owo_colors::DynColors| Value::Text {
+            text: text.to_string(),
+            style: Some(Style::new().color(color)),
+        };
+        let mut values = HashMap::from([
+            ("user", text(&user, self.data.colors.user_color)),
+            ("host", text(&host, self.data.colors.host_color)),
+            ("dir", text(&dir, self.data.colors.dir_color)),
+        ]);
+
+        let first_line = if let Some(mut info) = self.data.git_info {
+            let colors = match info.color {
+                Some(color) => PromptColors {
+                    git_color: color.to_dyn(),
+                    ..self.data.colors.clone()
+                },
+                None => self.data.colors.clone(),
+            };
             let nav_parts_owned = if let Some(current_dir) = &self.data.current_dir {
                 let relative = current_dir.strip_prefix(&info.work_dir).unwrap_or(current_dir);
                 let relative_str = relative.to_string_lossy();
@@ -262,41 +907,74 @@ impl PromptBuilder<Ready> {
             };
             let nav_parts = nav_parts_owned.iter().map(String::as_str).collect::<Vec<_>>();
             let email = info.user_email.as_deref();
+            let branch = shorten_branch(&info.branch, &self.data.branch);
+            if self.data.branch.is_active() {
+                info.indicators.compact_branch = Some(branch.clone());
+            }
+            let now = SystemTime::now();
+            let age = |time: Option<SystemTime>| now.duration_since(time?).ok();
+            info.indicators.commit_age = age(info.head_commit_time);
+            info.indicators.stale_fetch =
+                age(info.last_fetch_time).is_some_and(|age| age > self.data.stale_fetch_after);
             let display_mode = select_display_mode(
                 self.data.terminal_width,
                 email,
                 &info.repo_name,
-                &info.branch,
+                &branch,
+                &info.indicators,
                 &nav_parts,
-                &self.data.colors,
+                &colors,
+                &layout,
+            );
+            let git_line = GitLine {
+                mode: display_mode,
+                email,
+                repo_name: &info.repo_name,
+                branch: &branch,
+                full_branch: &info.branch,
+                indicators: &info.indicators,
+                nav_parts: &nav_parts,
+                colors: &colors,
+            };
+            values.insert("git", Value::Painted(git_line.git_block().text));
+            values.insert("repo", text(&info.repo_name, colors.git_color));
+            values.insert(
+                "branch",
+                Value::Text {
+                    text: branch.clone(),
+                    style: Some(colors.branch_style(&info.branch)),
+                },
             );
+            if let Some(email) = email {
+                values.insert("email", text(email, colors.user_color));
+            }
             format_git_prompt_line(
                 display_mode,
                 email,
                 &info.repo_name,
+                &branch,
                 &info.branch,
+                &info.indicators,
                 &nav_parts,
-                &self.data.colors,
+                &colors,
+                &layout,
             )
         } else {
-            build_non_git_path_string(
-                &dir,
-                &user,
-                &host,
-                &self.data.colors,
-                self.data.mode.as_str(),
-            )
+            build_non_git_path_string(&dir, &user, &host, &self.data.colors, &layout)
         };
 
         let prompt_symbol = if self.data.is_root { "#" } else { "$" };
-        let prompt = match self.data.mode {
-            PromptLayout::Inline => format!("{} {} ", first_line, prompt_symbol),
-            PromptLayout::DualLine => format!(
-                "{}\n└─ {} {} ",
-                first_line, self.data.exit_code, prompt_symbol
-            ),
+        let plain = |text: &str| Value::Text {
+            text: text.to_string(),
+            style: None,
         };
-        Ok(prompt)
+        values.insert("line", Value::Painted(first_line));
+        values.insert("exit", plain(&self.data.exit_code));
+        values.insert("symbol", plain(prompt_symbol));
+        for (name, output) in layout.outputs() {
+            values.insert(name, Value::Painted(output.to_string()));
+        }
+        Ok(self.data.template.render(&values))
     }
 }
 
@@ -325,18 +1003,19 @@ impl<State> PromptBuilder<State> {
         self.data.git_info = git_info;
         self
     }
-
 }
 
 pub struct LazyGitInfo {
-    repo: Option<gix::Repository>,
+    repo: Option<Vcs>,
+    config: GitConfig,
     cached: OnceCell<Option<GitInfo>>,
 }
 
 impl LazyGitInfo {
-    pub fn new(repo: Option<gix::Repository>) -> Self {
+    pub fn new(repo: Option<Vcs>, config: GitConfig) -> Self {
         Self {
             repo,
+            config,
             cached: OnceCell::new(),
         }
     }
@@ -346,7 +1025,7 @@ impl LazyGitInfo {
             .get_or_init(|| {
                 self.repo
                     .as_ref()
-                    .and_then(build_git_info)
+                    .and_then(|repo| repo.build_info(&self.config))
             })
             .as_ref()
     }
@@ -354,20 +1033,24 @@ impl LazyGitInfo {
 
 impl Default for LazyGitInfo {
     fn default() -> Self {
-        Self::new(discover_git_repo())
+        let config = GitConfig::default();
+        Self::new(discover_vcs(&config), config)
     }
 }
 
-
+#[allow(clippy::too_many_arguments)]
 pub fn select_display_mode(
     terminal_width: u16,
     email: Option<&str>,
     repo_name: &str,
     branch: &str,
+    indicators: &GitIndicators,
     nav_parts: &[&str],
-    _colors: &PromptColors,
+    colors: &PromptColors,
+    layout: &SegmentLayout,
 ) -> GitDisplayMode {
     let modes = [
+        GitDisplayMode::Extended,
         GitDisplayMode::Full,
         GitDisplayMode::Mini,
         GitDisplayMode::Micro,
@@ -375,7 +1058,12 @@ pub fn select_display_mode(
     ];
 
     for mode in modes {
-        let width = calculate_git_prompt_width(mode, email, repo_name, branch, nav_parts);
+        if mode == GitDisplayMode::Extended && indicators.default_branch.is_none() {
+            continue;
+        }
+        let width = calculate_git_prompt_width(
+            mode, email, repo_name, branch, indicators, nav_parts, colors, layout,
+        );
         if width <= terminal_width as usize {
             return mode;
         }
@@ -388,156 +1076,397 @@ fn visual_width(s: &str) -> usize {
     unicode_width::UnicodeWidthStr::width(s)
 }
 
+/// Apply the configured branch transforms: strip the first matching prefix,
+/// extract the ticket ID, then truncate in the middle to `max_length`.
+fn shorten_branch(branch: &str, config: &BranchConfig) -> String {
+    let mut branch = branch;
+    if let Some(prefix) = config
+        .strip_prefixes
+        .iter()
+        .flatten()
+        .find(|prefix| branch.len() > prefix.len() && branch.starts_with(prefix.as_str()))
+    {
+        branch = &branch[prefix.len()..];
+    }
+
+    if let Some(pattern) = &config.ticket_pattern
+        && let Ok(regex) = regex::Regex::new(pattern)
+        && let Some(captures) = regex.captures(branch)
+        && let Some(ticket) = captures.get(1).or_else(|| captures.get(0))
+    {
+        branch = ticket.as_str();
+    }
+
+    match config.max_length {
+        Some(max_width) => truncate_middle(branch, max_width),
+        None => branch.to_string(),
+    }
+}
+
+/// Truncate `text` to `max_width` columns by replacing its middle with `…`.
+fn truncate_middle(text: &str, max_width: usize) -> String {
+    if visual_width(text) <= max_width {
+        return text.to_string();
+    }
+    let available = max_width.saturating_sub(1);
+    let tail: String = take_columns(text.chars().rev(), available / 2)
+        .into_iter()
+        .rev()
+        .collect();
+    let head_width = available - visual_width(&tail);
+    let head: String = take_columns(text.chars(), head_width).into_iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+fn take_columns(chars: impl Iterator<Item = char>, max_width: usize) -> Vec<char> {
+    let mut used = 0;
+    chars
+        .take_while(|c| {
+            used += unicode_width::UnicodeWidthChar::width(*c).unwrap_or(0);
+            used <= max_width
+        })
+        .collect()
+}
+
+/// Plain text of the indicators shown for `mode`, without colors.
+///
+/// Shared by width calculation and rendering so both always agree.
+fn git_indicator_text(indicators: &GitIndicators, mode: GitDisplayMode) -> String {
+    let mut parts = Vec::new();
+    if indicators.linked_worktree {
+        parts.push(WORKTREE_MARKER.to_string());
+    }
+    for (active, marker) in [
+        (indicators.shallow, SHALLOW_MARKER),
+        (indicators.sparse_checkout, SPARSE_CHECKOUT_MARKER),
+        (indicators.partial_clone, PARTIAL_CLONE_MARKER),
+    ] {
+        if active {
+            parts.push(marker.to_string());
+        }
+    }
+    if mode.is_full()
+        && let Some(upstream) = &indicators.upstream
+        && let Some(text) = format_upstream_status(upstream)
+    {
+        parts.push(text);
+    }
+    if mode.is_full() && indicators.stale_fetch {
+        parts.push(STALE_FETCH_MARKER.to_string());
+    }
+    if let Some(status) = &indicators.status
+        && status.is_dirty()
+    {
+        match mode {
+            GitDisplayMode::Extended | GitDisplayMode::Full => {
+                parts.push(format_status_counts(status))
+            }
+            GitDisplayMode::Mini | GitDisplayMode::Micro | GitDisplayMode::Nano => {
+                parts.push("*".to_string())
+            }
+        }
+    }
+    if indicators.partial {
+        parts.push(PARTIAL_MARKER.to_string());
+    }
+    if mode.is_full()
+        && let Some(text) = format_submodule_summary(&indicators.submodules)
+    {
+        parts.push(text);
+    }
+    if mode.is_full() && indicators.stash > 0 {
+        parts.push(format!("${}", indicators.stash));
+    }
+    if mode.is_full()
+        && let Some(age) = indicators.commit_age
+    {
+        parts.push(format_relative_duration(age));
+    }
+    if mode == GitDisplayMode::Extended
+        && let Some(distance) = &indicators.default_branch
+    {
+        parts.push(format!(
+            "+{}/-{} vs {}",
+            distance.ahead, distance.behind, distance.name
+        ));
+    }
+    parts.join(" ")
+}
+
+/// Format a duration as its largest whole unit, e.g. `45s`, `3h` or `2d`.
+fn format_relative_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (value, unit) = match secs {
+        0..60 => (secs, "s"),
+        60..3_600 => (secs / 60, "m"),
+        3_600..86_400 => (secs / 3_600, "h"),
+        86_400..604_800 => (secs / 86_400, "d"),
+        604_800..31_536_000 => (secs / 604_800, "w"),
+        _ => (secs / 31_536_000, "y"),
+    };
+    format!("{}{}", value, unit)
+}
+
+/// Branch text of the compact display modes.
+fn compact_branch_text(indicators: &GitIndicators) -> &str {
+    indicators.compact_branch.as_deref().unwrap_or("…")
+}
+
+/// Text of the in-progress operation, which is shown in every display mode.
+fn git_operation_text(indicators: &GitIndicators) -> Option<String> {
+    let operation = indicators.operation.as_ref()?;
+    Some(match operation.progress {
+        Some((step, total)) => format!("{} {}/{}", operation.label, step, total),
+        None => operation.label.to_string(),
+    })
+}
+
+fn format_upstream_status(upstream: &UpstreamStatus) -> Option<String> {
+    match *upstream {
+        UpstreamStatus::NoUpstream => Some("∅".to_string()),
+        UpstreamStatus::Gone => Some("✗".to_string()),
+        UpstreamStatus::Tracking {
+            ahead: 0,
+            behind: 0,
+        } => None,
+        UpstreamStatus::Tracking { ahead, behind } => {
+            let mut text = String::new();
+            if ahead > 0 {
+                text.push_str(&format!("↑{}", ahead));
+            }
+            if behind > 0 {
+                text.push_str(&format!("↓{}", behind));
+            }
+            Some(text)
+        }
+    }
+}
+
+/// Submodule counts using the prefixes of `git submodule status`, e.g.
+/// `◫-1+2*1`, with a trailing `…` when not every submodule was inspected.
+fn format_submodule_summary(summary: &SubmoduleSummary) -> Option<String> {
+    let counts = [
+        ("-", summary.uninitialized),
+        ("+", summary.out_of_sync),
+        ("*", summary.dirty),
+    ]
+    .iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(symbol, count)| format!("{}{}", symbol, count))
+    .collect::<String>();
+    if counts.is_empty() {
+        return None;
+    }
+    let more = if summary.truncated { "…" } else { "" };
+    Some(format!("{}{}{}", SUBMODULE_MARKER, counts, more))
+}
+
+fn format_status_counts(status: &GitStatus) -> String {
+    [
+        ("+", status.staged),
+        ("~", status.modified),
+        ("-", status.deleted),
+        ("»", status.renamed),
+        ("?", status.untracked),
+        ("!", status.conflicted),
+    ]
+    .iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(symbol, count)| format!("{}{}", symbol, count))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+#[allow(clippy::too_many_arguments)]
 fn calculate_git_prompt_width(
     mode: GitDisplayMode,
     email: Option<&str>,
     repo_name: &str,
     branch: &str,
+    indicators: &GitIndicators,
     nav_parts: &[&str],
+    colors: &PromptColors,
+    layout: &SegmentLayout,
 ) -> usize {
-    let email_width = email.map_or(0, |e| {
-        if let Some((user, host)) = e.split_once('@') {
-            visual_width(user) + 1 + visual_width(host)
-        } else {
-            visual_width(e)
-        }
-    });
+    GitLine {
+        mode,
+        email,
+        repo_name,
+        branch,
+        full_branch: branch,
+        indicators,
+        nav_parts,
+        colors,
+    }
+    .render(layout)
+    .width
+}
+
+/// Warning marker rendered for a mismatched or missing identity.
+fn identity_warning_text(
+    email: Option<&str>,
+    identity: IdentityStatus,
+    show_full: bool,
+) -> Option<&'static str> {
+    match (email, identity) {
+        (Some(_), IdentityStatus::Mismatch) => Some("⚠ "),
+        (None, IdentityStatus::Missing) if show_full => Some("⚠ no email"),
+        (None, IdentityStatus::Missing) => Some("⚠"),
+        (None, IdentityStatus::Untrusted) if show_full => Some("⚠ untrusted repo"),
+        (None, IdentityStatus::Untrusted) => Some("⚠"),
+        _ => None,
+    }
+}
 
-    let repo_len = visual_width(repo_name);
-    let branch_len = visual_width(branch);
+/// Local part and domain of an email with a single `@`.
+fn split_email(email: &str) -> Option<(&str, &str)> {
+    email
+        .split_once('@')
+        .filter(|(_, domain)| !domain.contains('@'))
+}
+
+/// The git prompt line of one display mode, rendered segment by segment.
+///
+/// In a repository the username and hostname segments show the two halves
+/// of `user.email`.
+struct GitLine<'a> {
+    mode: GitDisplayMode,
+    email: Option<&'a str>,
+    repo_name: &'a str,
+    branch: &'a str,
+    /// Branch before shortening, which the branch color rules match.
+    full_branch: &'a str,
+    indicators: &'a GitIndicators,
+    nav_parts: &'a [&'a str],
+    colors: &'a PromptColors,
+}
+
+impl GitLine<'_> {
+    fn render(&self, layout: &SegmentLayout) -> RenderedSegment {
+        layout.render(self.colors, |kind| match kind {
+            SegmentKind::Username => Some(self.email_user()),
+            SegmentKind::Hostname => self.email_domain(),
+            SegmentKind::GitBranch => Some(self.git_block()),
+            SegmentKind::CurrentDirectory => Some(self.directory()),
+            SegmentKind::Command | SegmentKind::Env => None,
+        })
+    }
 
-    let nav_width = match mode {
-        GitDisplayMode::Full | GitDisplayMode::Mini | GitDisplayMode::Micro => {
-            let truncated = truncate_git_path(nav_parts);
-            visual_width(&truncated)
+    /// Paint part of the email, which turns into a warning on an identity
+    /// mismatch.
+    fn paint_email(&self, text: &str, color: owo_colors::DynColors) -> String {
+        match self.indicators.identity {
+            IdentityStatus::Mismatch => format!("{}", text.color(self.colors.warning).bold()),
+            _ => format!("{}", text.color(color)),
         }
-        GitDisplayMode::Nano => {
-            if nav_parts.is_empty() {
-                0
-            } else if nav_parts.len() == 1 {
-                visual_width(nav_parts[0])
-            } else {
-                3 + visual_width("› ") + visual_width(nav_parts.last().unwrap_or(&""))
-            }
+    }
+
+    /// The identity warning and the local part of the email, which only the
+    /// wider modes show, or the whole email if it is not an address. Always
+    /// rendered, even when empty, so that `@domain` keeps its `@`.
+    fn email_user(&self) -> RenderedSegment {
+        let show_full = self.mode.is_full() || self.mode == GitDisplayMode::Mini;
+        let mut segment = RenderedSegment::default();
+        if let Some(warning) =
+            identity_warning_text(self.email, self.indicators.identity, show_full)
+        {
+            segment.push(warning, warning.color(self.colors.warning).bold());
         }
-    };
+        let user = match self.email.map(|email| (email, split_email(email))) {
+            Some((_, Some((user, _)))) if show_full => user,
+            Some((email, None)) => email,
+            _ => "",
+        };
+        segment.push(user, self.paint_email(user, self.colors.user_color));
+        segment
+    }
 
-    match mode {
-        GitDisplayMode::Full => email_width + 3 + repo_len + 3 + branch_len + 2 + nav_width,
-        GitDisplayMode::Mini => email_width + 3 + repo_len + 3 + 1 + 2 + nav_width,
-        GitDisplayMode::Micro => {
-            let host_len = email.map_or(0, |e| {
-                if let Some((_, host)) = e.split_once('@') {
-                    visual_width(host)
-                } else {
-                    visual_width(e)
-                }
-            });
-            1 + host_len + 3 + repo_len + 3 + 1 + 2 + nav_width
-        }
-        GitDisplayMode::Nano => {
-            let host_len = email.map_or(0, |e| {
-                if let Some((_, host)) = e.split_once('@') {
-                    visual_width(host)
-                } else {
-                    visual_width(e)
-                }
-            });
-            let last_dir_width = if nav_parts.is_empty() {
-                0
-            } else if nav_parts.len() == 1 {
-                visual_width(nav_parts[0])
-            } else {
-                3 + visual_width("› ") + visual_width(nav_parts.last().unwrap_or(&""))
+    fn email_domain(&self) -> Option<RenderedSegment> {
+        let (_, domain) = split_email(self.email?)?;
+        Some(RenderedSegment {
+            warning: self.indicators.identity == IdentityStatus::Mismatch,
+            ..RenderedSegment::painted(domain, self.paint_email(domain, self.colors.host_color))
+        })
+    }
+
+    /// The git block, e.g. `[pulse : main ~2]`.
+    fn git_block(&self) -> RenderedSegment {
+        let (white, git) = (self.colors.white, self.colors.git_color);
+        let mut segment = RenderedSegment::painted("[", "[".color(white));
+        segment.push(self.repo_name, self.repo_name.color(git));
+        if self.mode != GitDisplayMode::Nano {
+            let branch = match self.mode.is_full() {
+                true => self.branch,
+                false => compact_branch_text(self.indicators),
             };
-            1 + host_len + 3 + repo_len + 2 + last_dir_width
+            segment.push(" : ", " : ".color(white));
+            let style = self.colors.branch_style(self.full_branch);
+            segment.push(branch, branch.style(style));
+        }
+        if let Some(text) = git_operation_text(self.indicators) {
+            let text = format!(" {}", text);
+            segment.push(&text, text.color(git).bold());
         }
+        let indicator_text = git_indicator_text(self.indicators, self.mode);
+        if !indicator_text.is_empty() {
+            let text = format!(" {}", indicator_text);
+            segment.push(&text, text.color(git));
+        }
+        segment.push("]", "]".color(white));
+        segment
     }
-}
 
-fn format_email_parts(email: &str, colors: &PromptColors, show_full: bool) -> String {
-    let mut result = String::new();
-    let email_parts: Vec<&str> = email.split('@').collect();
-    if email_parts.len() == 2 {
-        if show_full {
-            result.push_str(&format!("{}", email_parts[0].color(colors.user_color)));
+    /// The current directory relative to the work tree, after a `⊘` marker
+    /// when it is ignored. Always rendered, even at the work tree root.
+    fn directory(&self) -> RenderedSegment {
+        let style = match self.indicators.ignored_dir {
+            true => Style::new().color(self.colors.dir_color).dimmed().italic(),
+            false => Style::new().color(self.colors.dir_color),
+        };
+        let mut segment = RenderedSegment::default();
+        if self.indicators.ignored_dir && !self.nav_parts.is_empty() {
+            let marker = format!("{} ", IGNORED_DIR_MARKER);
+            segment.push(&marker, marker.style(style));
         }
-        result.push_str(&format!("{}", "@".color(colors.white)));
-        result.push_str(&format!("{}", email_parts[1].color(colors.host_color)));
-    } else {
-        result.push_str(&format!("{}", email.color(colors.user_color)));
+        if self.mode != GitDisplayMode::Nano {
+            let nav = truncate_git_path(self.nav_parts);
+            segment.push(&nav, nav.style(style));
+        } else if let [.., last] = self.nav_parts {
+            if self.nav_parts.len() > 1 {
+                segment.push("… › ", "… › ".color(self.colors.white));
+            }
+            segment.push(last, last.style(style));
+        }
+        segment
     }
-    result
 }
 
+/// Render the git prompt line. `branch` is shown as is, while the branch
+/// color rules match `full_branch`, the branch before shortening.
+#[allow(clippy::too_many_arguments)]
 pub fn format_git_prompt_line(
     mode: GitDisplayMode,
     email: Option<&str>,
     repo_name: &str,
     branch: &str,
+    full_branch: &str,
+    indicators: &GitIndicators,
     nav_parts: &[&str],
     colors: &PromptColors,
+    layout: &SegmentLayout,
 ) -> String {
-    let mut result = String::new();
-
-    match mode {
-        GitDisplayMode::Full => {
-            if let Some(email) = email {
-                result.push_str(&format_email_parts(email, colors, true));
-            }
-            result.push_str(&format!("{}", ": [".color(colors.white)));
-            result.push_str(&format!("{}", repo_name.color(colors.git_color)));
-            result.push_str(&format!("{}", " : ".color(colors.white)));
-            result.push_str(&format!("{}", branch.color(colors.git_color)));
-            result.push_str(&format!("{}", "] ".color(colors.white)));
-        }
-        GitDisplayMode::Mini => {
-            if let Some(email) = email {
-                result.push_str(&format_email_parts(email, colors, true));
-            }
-            result.push_str(&format!("{}", ": [".color(colors.white)));
-            result.push_str(&format!("{}", repo_name.color(colors.git_color)));
-            result.push_str(&format!("{}", " : ".color(colors.white)));
-            result.push_str(&format!("{}", "…".color(colors.git_color)));
-            result.push_str(&format!("{}", "] ".color(colors.white)));
-        }
-        GitDisplayMode::Micro => {
-            if let Some(email) = email {
-                result.push_str(&format_email_parts(email, colors, false));
-            }
-            result.push_str(&format!("{}", ": [".color(colors.white)));
-            result.push_str(&format!("{}", repo_name.color(colors.git_color)));
-            result.push_str(&format!("{}", " : ".color(colors.white)));
-            result.push_str(&format!("{}", "…".color(colors.git_color)));
-            result.push_str(&format!("{}", "] ".color(colors.white)));
-        }
-        GitDisplayMode::Nano => {
-            if let Some(email) = email {
-                result.push_str(&format_email_parts(email, colors, false));
-            }
-            result.push_str(&format!("{}", ": [".color(colors.white)));
-            result.push_str(&format!("{}", repo_name.color(colors.git_color)));
-            result.push_str(&format!("{}", "] ".color(colors.white)));
-            let last_dir = nav_parts.last().map(|s| s.to_string()).unwrap_or_default();
-            match nav_parts.len() {
-                0 => {}
-                1 => {
-                    result.push_str(&format!("{}", last_dir.color(colors.dir_color)));
-                }
-                _ => {
-                    result.push_str(&format!("{}", "… › ".color(colors.white)));
-                    result.push_str(&format!("{}", last_dir.color(colors.dir_color)));
-                }
-            }
-        }
-    }
-
-    if mode != GitDisplayMode::Nano {
-        let nav = truncate_git_path(nav_parts);
-        result.push_str(&format!("{}", nav.color(colors.dir_color)));
+    GitLine {
+        mode,
+        email,
+        repo_name,
+        branch,
+        full_branch,
+        indicators,
+        nav_parts,
+        colors,
     }
-
-    result
+    .render(layout)
+    .text
 }
 
 /// Get the current username from the operating system.
@@ -620,8 +1549,8 @@ pub fn generate_prompt(config: &Config) -> Result<String> {
     let host = get_hostname()?;
     let dir = get_current_directory()?;
     let current_dir = std::env::current_dir()?;
-    let repo = discover_git_repo();
-    let git_info = LazyGitInfo::new(repo);
+    let repo = discover_vcs(&config.git);
+    let git_info = LazyGitInfo::new(repo, config.git.clone());
     let git_info = git_info.get().cloned();
     let exit_code = get_exit_code();
     let terminal_width = get_terminal_width().unwrap_or(DEFAULT_TERM_WIDTH as u16);
@@ -647,744 +1576,3709 @@ pub fn get_hostname() -> Result<String> {
         .map_err(|e| anyhow::anyhow!("Unable to get hostname: {}", e))
 }
 
-
-fn get_git_branch_from_repo(repo: &gix::Repository) -> Option<String> {
+fn get_git_branch_from_repo(
+    repo: &gix::Repository,
+    strategies: &[DetachedHeadStrategy],
+) -> Option<String> {
     let mut head = repo.head().ok()?;
     if head.is_detached() {
-        head.try_peel_to_id_in_place()
-            .ok()
-            .flatten()
-            .map(|id| id.to_hex_with_len(7).to_string())
+        let id = head.try_peel_to_id_in_place().ok().flatten()?;
+        Some(describe_detached_head(repo, id, strategies))
     } else {
         head.referent_name().map(|name| name.shorten().to_string())
     }
 }
 
-/// Get the exit code from environment
-pub fn get_exit_code() -> String {
-    std::env::var("PIPESTATUS")
-        .or_else(|_| std::env::var("LAST_EXIT_CODE"))
-        .unwrap_or_else(|_| "0".to_string())
+/// Name a detached HEAD by trying `strategies` in order.
+///
+/// The short hash is used when no strategy yields a name.
+fn describe_detached_head(
+    repo: &gix::Repository,
+    id: gix::Id<'_>,
+    strategies: &[DetachedHeadStrategy],
+) -> String {
+    // Exact and nearest tag share one describe walk.
+    let nearest_tag = OnceCell::new();
+    let nearest_tag = || nearest_tag.get_or_init(|| find_nearest_tag(id));
+
+    strategies
+        .iter()
+        .find_map(|strategy| match strategy {
+            DetachedHeadStrategy::ExactTag => nearest_tag()
+                .as_ref()
+                .filter(|(_, depth)| *depth == 0)
+                .map(|(name, _)| name.clone()),
+            DetachedHeadStrategy::NearestTag => {
+                nearest_tag().as_ref().map(|(name, depth)| match depth {
+                    0 => name.clone(),
+                    depth => format!("{}+{}", name, depth),
+                })
+            }
+            DetachedHeadStrategy::RemoteBranch => find_remote_branch_at(repo, id.detach()),
+        })
+        .unwrap_or_else(|| id.to_hex_with_len(7).to_string())
 }
 
-/// Check if current user is root
-pub fn is_root_user() -> bool {
-    users::get_current_uid() == 0
+/// Find the closest tag reachable from `id` and the number of commits
+/// between them, like `git describe --tags`.
+fn find_nearest_tag(id: gix::Id<'_>) -> Option<(String, u32)> {
+    use gix::commit::describe::SelectRef;
+
+    let commit = id.object().ok()?.try_into_commit().ok()?;
+    let resolution = commit
+        .describe()
+        .names(SelectRef::AllTags)
+        .try_resolve()
+        .ok()??;
+    let outcome = resolution.outcome;
+    Some((outcome.name?.to_string(), outcome.depth))
 }
 
+/// Find a remote-tracking branch that points at `id`, e.g. `origin/main`.
+fn find_remote_branch_at(repo: &gix::Repository, id: gix::ObjectId) -> Option<String> {
+    let references = repo.references().ok()?;
+    let remote_branches = references.remote_branches().ok()?;
+    remote_branches
+        .filter_map(Result::ok)
+        .filter(|reference| !reference.name().as_bstr().ends_with(b"/HEAD"))
+        .find_map(|mut reference| {
+            let target = reference.peel_to_id_in_place().ok()?;
+            (target == id).then(|| reference.name().shorten().to_string())
+        })
+}
 
-fn build_git_info(repo: &gix::Repository) -> Option<GitInfo> {
-    let work_dir = repo.work_dir()?;
-    let work_dir = std::fs::canonicalize(work_dir).ok()?;
-    let repo_name = work_dir.file_name()?.to_str()?.to_string();
-    let branch = get_git_branch_from_repo(repo)
-        .unwrap_or_else(|| "unknown".to_string());
+/// Describe the operation in progress, based on gix's `repo.state()`.
+///
+/// Rebases and `git am` sessions additionally report their step counter and
+/// the branch being rebased from the state directory inside `.git`.
+fn get_git_operation_from_repo(repo: &gix::Repository) -> Option<GitOperation> {
+    use gix::state::InProgress;
+
+    let label = match repo.state()? {
+        InProgress::ApplyMailbox => "AM",
+        InProgress::ApplyMailboxRebase => "AM/REBASE",
+        InProgress::Bisect => "BISECTING",
+        InProgress::CherryPick | InProgress::CherryPickSequence => "CHERRY-PICKING",
+        InProgress::Merge => "MERGING",
+        InProgress::Rebase => "REBASE",
+        InProgress::RebaseInteractive => "REBASE-i",
+        InProgress::Revert | InProgress::RevertSequence => "REVERTING",
+    };
 
-    let config = repo.config_snapshot();
-    let user_email = config.string("user.email").map(|s| s.to_string());
+    let git_dir = repo.path();
+    let merge_dir = git_dir.join("rebase-merge");
+    let apply_dir = git_dir.join("rebase-apply");
+    let (progress, head_name) = if merge_dir.is_dir() {
+        (
+            read_rebase_progress(&merge_dir, "msgnum", "end"),
+            read_rebase_head_name(&merge_dir),
+        )
+    } else if apply_dir.is_dir() {
+        (
+            read_rebase_progress(&apply_dir, "next", "last"),
+            read_rebase_head_name(&apply_dir),
+        )
+    } else {
+        (None, None)
+    };
 
-    Some(GitInfo {
-        repo_name,
-        branch,
-        user_email,
-        work_dir,
+    Some(GitOperation {
+        label,
+        progress,
+        head_name,
     })
 }
 
-/// Truncate git path for display
-pub fn truncate_git_path(parts: &[&str]) -> String {
-    if parts.is_empty() {
-        String::new()
-    } else if parts.len() > TRUNCATION_THRESHOLD {
-        format!("… {}", parts[parts.len() - TRUNCATION_THRESHOLD..].join(" › "))
-    } else {
-        parts.join(" › ")
-    }
+fn read_rebase_progress(
+    state_dir: &std::path::Path,
+    step_file: &str,
+    total_file: &str,
+) -> Option<(usize, usize)> {
+    let read = |name: &str| -> Option<usize> {
+        std::fs::read_to_string(state_dir.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some((read(step_file)?, read(total_file)?))
 }
 
-/// Truncate non-git path for display
-pub fn truncate_non_git_path(root: &str, parts: &[&str]) -> String {
-    if parts.is_empty() {
-        root.to_string()
-    } else if parts.len() > TRUNCATION_THRESHOLD {
-        format!("{} … {}", root, parts[parts.len() - TRUNCATION_THRESHOLD..].join(" › "))
-    } else {
-        format!("{} {}", root, parts.join(" › "))
-    }
+fn read_rebase_head_name(state_dir: &std::path::Path) -> Option<String> {
+    let head_name = std::fs::read_to_string(state_dir.join("head-name")).ok()?;
+    let head_name = head_name.trim();
+    head_name.strip_prefix("refs/heads/").map(str::to_string)
 }
 
-/// Builds the user@host:path string for non-git mode.
-///
-/// This helper handles:
-/// - Path normalization (extracting root ~ or / and navigation portion)
-/// - Navigation splitting by '/'
-/// - Truncation of long paths
-/// - Building the colored user@host:path string
-///
-/// # Arguments
-/// * `dir` - The current directory path
-/// * `user` - The username string
-/// * `host` - The hostname string
-/// * `user_color` - Color for the username
-/// * `host_color` - Color for the hostname
-/// * `dir_color` - Color for the directory path
-/// * `white` - Color for the separator characters (@ and :)
-/// * `mode` - Display mode ("Inline" or "DualLine")
-///
-/// # Arguments
-/// * `dir` - The current directory path
-/// * `user` - The username string
-/// * `host` - The hostname string
-/// * `colors` - The PromptColors struct containing color definitions
-/// * `mode` - Display mode ("Inline" or "DualLine")
+/// Determine how the current branch relates to its configured upstream.
 ///
-/// # Returns
-/// A formatted string with the user@host:path components colored
-pub fn build_non_git_path_string(
-    dir: &str,
-    user: &str,
-    host: &str,
-    colors: &PromptColors,
-    _mode: &str,
-) -> String {
-    let (root, nav) = if dir == "~" {
-        ("~", "".to_string())
-    } else if dir.starts_with("~/") {
-        (
-            "~",
-            dir.strip_prefix("~/")
-                .map(|s| s.to_string())
-                .unwrap_or_default(),
-        )
-    } else {
-        (
-            "/",
-            dir.strip_prefix("/")
-                .map(|s| s.to_string())
-                .unwrap_or_default(),
-        )
+/// Returns `None` for a detached or unborn HEAD, where tracking does not
+/// apply. Only local refs are consulted; nothing is fetched.
+fn get_upstream_status_from_repo(repo: &gix::Repository) -> Option<UpstreamStatus> {
+    let head_name = repo.head_name().ok()??;
+    let local_id = repo.head_id().ok()?.detach();
+    let Some(tracking_ref) = resolve_upstream_ref(repo, head_name.as_ref()) else {
+        return Some(UpstreamStatus::NoUpstream);
     };
-    let nav_parts: Vec<&str> = nav.split('/').filter(|s| !s.is_empty()).collect();
-    let path_display = truncate_non_git_path(root, &nav_parts);
-
-    let mut result = String::new();
-    result.push_str(&format!("{}", user.color(colors.user_color)));
-    result.push_str(&format!("{}", "@".color(colors.white)));
-    result.push_str(&format!("{}", host.color(colors.host_color)));
-    result.push_str(&format!("{}", ":".color(colors.white)));
-    result.push_str(&format!("{}", path_display.color(colors.dir_color)));
-    result
+    let Some(mut upstream) = repo.try_find_reference(tracking_ref.as_ref()).ok()? else {
+        return Some(UpstreamStatus::Gone);
+    };
+    let upstream_id = upstream.peel_to_id_in_place().ok()?.detach();
+    let (ahead, behind) = count_ahead_behind(repo, local_id, upstream_id)?;
+    Some(UpstreamStatus::Tracking { ahead, behind })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serial_test::serial;
-    use tempfile::TempDir;
+/// Distance between HEAD and the default branch, unless HEAD is on it or
+/// at the same commit.
+fn get_default_branch_distance(
+    repo: &gix::Repository,
+    configured: Option<&str>,
+) -> Option<DefaultBranchDistance> {
+    let (name, default_id) = resolve_default_branch(repo, configured)?;
+    if let Ok(Some(head_name)) = repo.head_name()
+        && head_name.shorten() == name.as_str()
+    {
+        return None;
+    }
+    let head_id = repo.head_id().ok()?.detach();
+    let (ahead, behind) = count_ahead_behind(repo, head_id, default_id)?;
+    if ahead == 0 && behind == 0 {
+        return None;
+    }
+    Some(DefaultBranchDistance {
+        name,
+        ahead,
+        behind,
+    })
+}
 
-    struct DirGuard {
-        original: PathBuf,
+/// Resolve the default branch from `refs/remotes/origin/HEAD`, falling back
+/// to a local `main` or `master` branch.
+///
+/// A `configured` branch (`pulse.defaultBranch`) is looked up as
+/// `origin/<name>`, then as a local branch, without any fallback.
+fn resolve_default_branch(
+    repo: &gix::Repository,
+    configured: Option<&str>,
+) -> Option<(String, gix::ObjectId)> {
+    let find_id = |name: &str| -> Option<gix::ObjectId> {
+        let mut reference = repo.try_find_reference(name).ok()??;
+        Some(reference.peel_to_id_in_place().ok()?.detach())
+    };
+    if let Some(name) = configured {
+        let id = find_id(&format!("refs/remotes/origin/{}", name))
+            .or_else(|| find_id(&format!("refs/heads/{}", name)))?;
+        return Some((name.to_string(), id));
+    }
+    if let Ok(Some(mut origin_head)) = repo.try_find_reference("refs/remotes/origin/HEAD")
+        && let Some(target) = origin_head.target().try_name().map(|name| name.to_owned())
+        && let Ok(id) = origin_head.peel_to_id_in_place()
+    {
+        let name = target.shorten().to_string();
+        let name = name.strip_prefix("origin/").unwrap_or(&name).to_string();
+        return Some((name, id.detach()));
     }
+    ["main", "master"].into_iter().find_map(|name| {
+        let id = find_id(&format!("refs/heads/{}", name))?;
+        Some((name.to_string(), id))
+    })
+}
 
-    impl DirGuard {
-        fn new(target: &std::path::Path) -> Self {
-            let original = std::env::current_dir().expect("current dir should be available");
-            std::env::set_current_dir(target).expect("set current dir to temp repo");
-            Self { original }
-        }
+/// Resolve the tracking ref for `branch` from `branch.<name>.remote` and
+/// `branch.<name>.merge`, including upstreams on the local repository (`.`).
+fn resolve_upstream_ref(
+    repo: &gix::Repository,
+    branch: &gix::refs::FullNameRef,
+) -> Option<gix::refs::FullName> {
+    use gix::remote::Direction;
+
+    let merge_ref = repo
+        .branch_remote_ref_name(branch, Direction::Fetch)?
+        .ok()?;
+    let remote = repo.branch_remote_name(branch.shorten(), Direction::Fetch)?;
+    if remote.as_bstr() == "." {
+        return Some(merge_ref.into_owned());
     }
+    repo.branch_remote_tracking_ref_name(branch, Direction::Fetch)?
+        .ok()
+        .map(|name| name.into_owned())
+}
 
-    impl Drop for DirGuard {
-        fn drop(&mut self) {
-            let _ = std::env::set_current_dir(&self.original);
+/// Count commits reachable only from `local` and only from `upstream`.
+///
+/// Both histories are walked newest-first and the walk stops once every
+/// queued commit is reachable from both sides, so only the diverged part of
+/// the graph is visited.
+fn count_ahead_behind(
+    repo: &gix::Repository,
+    local: gix::ObjectId,
+    upstream: gix::ObjectId,
+) -> Option<(usize, usize)> {
+    const LOCAL: u8 = 1;
+    const UPSTREAM: u8 = 2;
+    const BOTH: u8 = LOCAL | UPSTREAM;
+
+    let mut commits = HashMap::new();
+    let mut flags: HashMap<gix::ObjectId, u8> = HashMap::new();
+    let mut queue = std::collections::BinaryHeap::new();
+    // Commits in `queue`, each at most once, and how many of them are not
+    // yet reachable from both sides.
+    let mut queued = std::collections::HashSet::new();
+    let mut pending = 0;
+    for (id, flag) in [(local, LOCAL), (upstream, UPSTREAM)] {
+        *flags.entry(id).or_default() |= flag;
+    }
+    for id in [local, upstream] {
+        if queued.insert(id) {
+            pending += usize::from(flags[&id] != BOTH);
+            queue.push((commit_info(repo, &mut commits, id)?.0, id));
         }
     }
 
-    fn init_temp_git_repo() -> TempDir {
-        let temp_dir = tempfile::tempdir().expect("create temp dir");
-        let _repo = gix::init(temp_dir.path()).expect("init temp git repo");
-        temp_dir
+    while pending > 0 {
+        let Some((_, id)) = queue.pop() else {
+            break;
+        };
+        queued.remove(&id);
+        let flag = flags[&id];
+        if flag != BOTH {
+            pending -= 1;
+        }
+        let parents = commit_info(repo, &mut commits, id)?.1.clone();
+        for parent in parents {
+            let parent_flag = flags.entry(parent).or_default();
+            if *parent_flag | flag == *parent_flag {
+                continue;
+            }
+            *parent_flag |= flag;
+            let is_common = *parent_flag == BOTH;
+            if queued.contains(&parent) {
+                pending -= usize::from(is_common);
+            } else {
+                queued.insert(parent);
+                pending += usize::from(!is_common);
+                queue.push((commit_info(repo, &mut commits, parent)?.0, parent));
+            }
+        }
     }
 
-    fn repo_name_from_path(path: &std::path::Path) -> String {
-        path.file_name()
-            .and_then(|name| name.to_str())
-            .expect("temp repo dir name")
-            .to_string()
+    // Ties and skewed commit times can visit a commit before all of its
+    // descendants, so push the common flag into ancestors that were already
+    // visited from one side only.
+    let mut common = queue.into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+    while let Some(id) = common.pop() {
+        for parent in commit_info(repo, &mut commits, id)?.1.clone() {
+            if let Some(flag) = flags.get_mut(&parent)
+                && *flag != BOTH
+            {
+                *flag = BOTH;
+                common.push(parent);
+            }
+        }
     }
 
-    #[test]
-    #[serial]
-    fn test_is_in_git_repo() {
-        let temp_dir = init_temp_git_repo();
-        let repo = discover_git_repo_in(temp_dir.path());
-        assert!(repo.is_some());
-    }
+    let ahead = flags.values().filter(|flag| **flag == LOCAL).count();
+    let behind = flags.values().filter(|flag| **flag == UPSTREAM).count();
+    Some((ahead, behind))
+}
 
-    #[test]
-    fn test_get_username() {
-        let username = get_username();
-        assert!(username.is_ok());
-        let uname = username.expect("username should be Ok after is_ok check");
-        assert!(!uname.is_empty());
+/// Commit time and parents of `id`, decoded once and kept in `commits`.
+fn commit_info<'a>(
+    repo: &gix::Repository,
+    commits: &'a mut HashMap<gix::ObjectId, (i64, Vec<gix::ObjectId>)>,
+    id: gix::ObjectId,
+) -> Option<&'a (i64, Vec<gix::ObjectId>)> {
+    use gix::objs::commit::ref_iter::Token;
+    use std::collections::hash_map::Entry;
+
+    let entry = match commits.entry(id) {
+        Entry::Occupied(entry) => return Some(entry.into_mut()),
+        Entry::Vacant(entry) => entry,
+    };
+    // One pass over the headers, which end with the committer.
+    let commit = repo.find_object(id).ok()?.try_into_commit().ok()?;
+    let mut parents = Vec::new();
+    for token in commit.iter() {
+        match token.ok()? {
+            Token::Parent { id } => parents.push(id),
+            Token::Committer { signature } => {
+                return Some(entry.insert((signature.time.seconds, parents)));
+            }
+            _ => {}
+        }
     }
+    None
+}
 
-    #[test]
-    fn test_get_current_directory() {
-        let cwd = get_current_directory();
-        assert!(cwd.is_ok());
-        let cwd_str = cwd.expect("cwd should be Ok after is_ok check");
-        assert!(!cwd_str.is_empty());
-        // Should start with / or ~
-        assert!(cwd_str.starts_with('/') || cwd_str.starts_with('~'));
+/// Count working-tree changes through gix's status API.
+///
+/// Unstaged changes come from the index/worktree status iterator, while
+/// staged changes are derived by comparing the index against the HEAD tree.
+fn get_git_status_from_repo(repo: &gix::Repository) -> Option<GitStatus> {
+    use gix::status::index_worktree::iter::{Item, Summary};
+
+    let mut status = GitStatus::default();
+    // gix fails to prepare submodule status on an unborn HEAD, so a fresh
+    // repository only needs a walk for untracked files.
+    if repo.head().ok()?.is_unborn() {
+        status.untracked = count_untracked_files(repo)?;
+        count_staged_changes(repo, &mut status)?;
+        return Some(status);
     }
 
-    #[test]
-    fn test_get_hostname() {
-        let hostname = get_hostname();
-        assert!(hostname.is_ok());
-        let hname = hostname.expect("hostname should be Ok after is_ok check");
-        assert!(!hname.is_empty());
+    let items = repo
+        .status(gix::progress::Discard)
+        .ok()?
+        .index_worktree_submodules(None)
+        .into_index_worktree_iter(Vec::new())
+        .ok()?;
+    let work_dir = repo.work_dir()?;
+    for item in items {
+        let item = item.ok()?;
+        match item.summary() {
+            Some(Summary::Modified | Summary::TypeChange) => status.modified += 1,
+            Some(Summary::Removed) => status.deleted += 1,
+            Some(Summary::Added) => match &item {
+                Item::DirectoryContents { entry, .. } if is_empty_dir(work_dir, entry) => {}
+                _ => status.untracked += 1,
+            },
+            Some(Summary::Renamed | Summary::Copied) => status.renamed += 1,
+            Some(Summary::Conflict) => status.conflicted += 1,
+            Some(Summary::IntentToAdd) => status.staged += 1,
+            None => {}
+        }
     }
 
-    #[test]
-    #[serial]
-    fn test_get_git_branch() {
-        let temp_dir = init_temp_git_repo();
-        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
-        let branch = get_git_branch_from_repo(&repo);
-        assert!(branch.is_some());
-        let branch_name = branch.expect("branch should be Some after is_some check");
-        assert!(!branch_name.is_empty());
-    }
+    count_staged_changes(repo, &mut status)?;
+    Some(status)
+}
 
-    #[test]
-    #[serial]
-    fn test_get_git_info() {
-        let temp_dir = init_temp_git_repo();
-        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
-        let expected = repo_name_from_path(temp_dir.path());
-        let info = build_git_info(&repo).expect("build git info");
-        assert_eq!(info.repo_name, expected);
-        assert!(!info.branch.is_empty());
-        assert!(info.work_dir.is_absolute());
-    }
+fn count_untracked_files(repo: &gix::Repository) -> Option<usize> {
+    use gix::dir::entry::Status;
+    use gix::dir::walk::EmissionMode;
+
+    let index = repo.index_or_empty().ok()?;
+    let options = repo
+        .dirwalk_options()
+        .ok()?
+        .emit_untracked(EmissionMode::CollapseDirectory);
+    let mut collect = gix::dir::walk::delegate::Collect::default();
+    repo.dirwalk(&index, None::<&str>, options, &mut collect)
+        .ok()?;
+    let work_dir = repo.work_dir()?;
+    Some(
+        collect
+            .unorded_entries
+            .iter()
+            .filter(|(entry, _)| {
+                entry.status == Status::Untracked && !is_empty_dir(work_dir, entry)
+            })
+            .count(),
+    )
+}
 
-    #[test]
-    fn test_truncate_git_path_empty() {
-        assert_eq!(truncate_git_path(&[]), "");
-    }
+/// Whether a collapsed untracked entry is a directory without any files
+/// below it, which git leaves out of its status.
+fn is_empty_dir(work_dir: &std::path::Path, entry: &gix::dir::Entry) -> bool {
+    entry.disk_kind == Some(gix::dir::entry::Kind::Directory)
+        && !contains_files(&work_dir.join(gix::path::from_byte_slice(&entry.rela_path)))
+}
 
-    #[test]
-    fn test_truncate_git_path_three_parts() {
-        assert_eq!(
-            truncate_git_path(&["src", "main", "rust"]),
-            "src › main › rust"
-        );
-    }
+/// Whether `dir` or any directory below it holds something other than a
+/// directory.
+fn contains_files(dir: &std::path::Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries
+        .filter_map(Result::ok)
+        .any(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => contains_files(&entry.path()),
+            _ => true,
+        })
+}
 
-    #[test]
-    fn test_truncate_git_path_four_parts() {
-        assert_eq!(truncate_git_path(&["a", "b", "c", "d"]), "… b › c › d");
+/// Compare index entries with the HEAD tree, counting additions, removals
+/// and modifications as staged. An added entry whose blob id matches a
+/// removed one is counted as a staged rename instead.
+fn count_staged_changes(repo: &gix::Repository, status: &mut GitStatus) -> Option<()> {
+    let mut head_entries = HashMap::new();
+    if let Ok(commit) = repo.head_commit() {
+        let mut recorder = gix::traverse::tree::Recorder::default();
+        commit
+            .tree()
+            .ok()?
+            .traverse()
+            .breadthfirst(&mut recorder)
+            .ok()?;
+        for entry in recorder.records {
+            if entry.mode.is_no_tree() {
+                head_entries.insert(entry.filepath, (entry.oid, entry.mode.kind()));
+            }
+        }
     }
 
-    #[test]
-    fn test_truncate_git_path_more_than_four() {
-        assert_eq!(truncate_git_path(&["x", "y", "z", "a", "b"]), "… z › a › b");
+    let index = repo.index_or_empty().ok()?;
+    let mut added = Vec::new();
+    for entry in index.entries() {
+        let path = entry.path(&index);
+        let head_entry = head_entries.remove(path);
+        if entry.stage() != 0 || entry.mode.is_sparse() {
+            continue;
+        }
+        let kind = entry.mode.to_tree_entry_mode().map(|mode| mode.kind());
+        match head_entry {
+            Some((id, head_kind)) => {
+                if id != entry.id || Some(head_kind) != kind {
+                    status.staged += 1;
+                }
+            }
+            None => added.push(entry.id),
+        }
     }
 
-    #[test]
-    fn test_truncate_non_git_path_inline() {
-        assert_eq!(truncate_non_git_path("~", &["a", "b"]), "~ a › b");
+    for (id, _) in head_entries.into_values() {
+        if let Some(pos) = added.iter().position(|added_id| *added_id == id) {
+            added.swap_remove(pos);
+            status.renamed += 1;
+        } else {
+            status.staged += 1;
+        }
+    }
+    status.staged += added.len();
+    Some(())
+}
+
+/// Get the exit code from environment
+pub fn get_exit_code() -> String {
+    std::env::var("PIPESTATUS")
+        .or_else(|_| std::env::var("LAST_EXIT_CODE"))
+        .unwrap_or_else(|_| "0".to_string())
+}
+
+/// Check if current user is root
+pub fn is_root_user() -> bool {
+    users::get_current_uid() == 0
+}
+
+/// Count the entries of the `refs/stash` reflog, one per stash.
+fn get_stash_count_from_repo(repo: &gix::Repository) -> usize {
+    let Ok(Some(stash)) = repo.try_find_reference("refs/stash") else {
+        return 0;
+    };
+    let mut log = stash.log_iter();
+    match log.all() {
+        Ok(Some(entries)) => entries.filter(Result::is_ok).count(),
+        _ => 0,
+    }
+}
+
+fn is_linked_worktree(repo: &gix::Repository) -> bool {
+    matches!(
+        repo.kind(),
+        gix::repository::Kind::WorkTree { is_linked: true }
+    )
+}
+
+/// Name of the main repository a linked worktree belongs to, derived from
+/// the shared git dir (`<main>/.git`, or `<main>.git` for bare repositories).
+fn get_main_repo_name(repo: &gix::Repository) -> Option<String> {
+    let common_dir = std::fs::canonicalize(repo.common_dir()).ok()?;
+    let main_dir = if common_dir.file_name()? == ".git" {
+        common_dir.parent()?
+    } else {
+        &common_dir
+    };
+    let name = main_dir.file_name()?.to_str()?;
+    Some(name.strip_suffix(".git").unwrap_or(name).to_string())
+}
+
+/// `owner/repo` from the `origin` remote URL, e.g.
+/// `git@github.com:owner/repo.git`.
+fn get_remote_repo_name(repo: &gix::Repository) -> Option<String> {
+    let remote = repo.find_remote("origin").ok()?;
+    let url = remote.url(gix::remote::Direction::Fetch)?;
+    repo_name_from_url_path(&url.path.to_string())
+}
+
+fn repo_name_from_url_path(path: &str) -> Option<String> {
+    let mut parts = path.rsplit('/').filter(|part| !part.is_empty());
+    let name = parts.next()?;
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    Some(match parts.next() {
+        Some(owner) => format!("{}/{}", owner, name),
+        None => name.to_string(),
+    })
+}
+
+/// Resolve the repository name by trying `strategies` in order.
+///
+/// The directory name is used when no strategy yields a name.
+fn resolve_repo_name(
+    repo: &gix::Repository,
+    work_dir: &std::path::Path,
+    linked_worktree: bool,
+    strategies: &[RepoNameStrategy],
+) -> Option<String> {
+    let directory_name = || {
+        linked_worktree
+            .then(|| get_main_repo_name(repo))
+            .flatten()
+            .or_else(|| work_dir.file_name()?.to_str().map(str::to_string))
+    };
+
+    strategies
+        .iter()
+        .find_map(|strategy| match strategy {
+            RepoNameStrategy::Directory => directory_name(),
+            RepoNameStrategy::RemoteUrl => get_remote_repo_name(repo),
+            RepoNameStrategy::WorkspaceMember => {
+                let current_dir = std::fs::canonicalize(std::env::current_dir().ok()?).ok()?;
+                find_workspace_member(&current_dir, work_dir)
+            }
+        })
+        .or_else(directory_name)
+}
+
+/// Resolve the repository name like [`resolve_repo_name`], prefixed by the
+/// name of the superproject when the repository is a submodule.
+fn resolve_qualified_repo_name(
+    repo: &gix::Repository,
+    work_dir: &std::path::Path,
+    linked_worktree: bool,
+    strategies: &[RepoNameStrategy],
+    git_config: &GitConfig,
+) -> Option<String> {
+    let repo_name = resolve_repo_name(repo, work_dir, linked_worktree, strategies)?;
+    Some(match get_superproject_name(work_dir, git_config) {
+        Some(superproject) => format!("{}{}{}", superproject, SUBMODULE_SEPARATOR, repo_name),
+        None => repo_name,
+    })
+}
+
+/// Whether `core.sparseCheckout` is enabled and the sparse pattern file
+/// exists.
+fn is_sparse_checkout(repo: &gix::Repository) -> bool {
+    let enabled = repo
+        .config_snapshot()
+        .boolean("core.sparseCheckout")
+        .unwrap_or(false);
+    enabled && repo.git_dir().join("info/sparse-checkout").is_file()
+}
+
+/// Whether `dir` is inside the git directory or matched by an exclude
+/// pattern, checking each directory from the worktree root down.
+fn is_ignored_dir(
+    repo: &gix::Repository,
+    work_dir: &std::path::Path,
+    dir: &std::path::Path,
+) -> bool {
+    use gix::worktree::stack::state::ignore::Source;
+
+    let in_git_dir = [repo.git_dir(), repo.common_dir()]
+        .iter()
+        .filter_map(|git_dir| std::fs::canonicalize(git_dir).ok())
+        .any(|git_dir| dir.starts_with(git_dir));
+    if in_git_dir {
+        return true;
+    }
+    let Ok(relative) = dir.strip_prefix(work_dir) else {
+        return false;
+    };
+    if relative.as_os_str().is_empty() {
+        return false;
+    }
+    let Ok(index) = repo.index_or_empty() else {
+        return false;
+    };
+    let Ok(mut excludes) = repo.excludes(&index, None, Source::WorktreeThenIdMappingIfNotSkipped)
+    else {
+        return false;
+    };
+    let mut path = PathBuf::new();
+    relative.components().any(|component| {
+        path.push(component);
+        excludes
+            .at_path(&path, Some(true))
+            .is_ok_and(|platform| platform.is_excluded())
+    })
+}
+
+/// Whether any remote is a promisor remote, i.e. the repository is a partial
+/// clone.
+fn is_partial_clone(repo: &gix::Repository) -> bool {
+    let config = repo.config_snapshot();
+    repo.remote_names().iter().any(|name| {
+        config
+            .boolean(format!("remote.{}.promisor", name).as_str())
+            .unwrap_or(false)
+    })
+}
+
+/// Name of the superproject when `work_dir` is the worktree of one of its
+/// submodules.
+///
+/// Looking for the superproject is a second discovery above the work tree,
+/// so it honors the same limits: nothing is discovered in excluded
+/// directories, on network and FUSE filesystems (unless
+/// `git.discovery.slow_filesystems` is `full`) or above a ceiling directory,
+/// and safe mode applies.
+fn get_superproject_name(work_dir: &std::path::Path, git_config: &GitConfig) -> Option<String> {
+    let parent = work_dir.parent()?;
+    let discovery = &git_config.discovery;
+    if discovery::is_excluded(parent, discovery.exclude())
+        || (discovery.slow_filesystems() != SlowFilesystemMode::Full
+            && is_on_slow_filesystem(parent))
+    {
+        return None;
+    }
+    let superproject =
+        discover_git_repo_below(parent, &discovery::ceiling_dirs(), git_config.safe_mode())?;
+    let super_work_dir = std::fs::canonicalize(superproject.work_dir()?).ok()?;
+    let relative = work_dir.strip_prefix(&super_work_dir).ok()?;
+    let is_submodule = superproject.submodules().ok()??.any(|submodule| {
+        submodule
+            .path()
+            .is_ok_and(|path| gix::path::from_bstr(path.as_ref()).as_ref() == relative)
+    });
+    if !is_submodule {
+        return None;
+    }
+    resolve_repo_name(
+        &superproject,
+        &super_work_dir,
+        is_linked_worktree(&superproject),
+        &[RepoNameStrategy::Directory],
+    )
+}
+
+/// Summarize submodules that are uninitialized, out of sync with the
+/// recorded commit, or dirty, inspecting at most `limit` of them.
+fn get_submodule_summary(repo: &gix::Repository, limit: usize) -> SubmoduleSummary {
+    use gix::submodule::config::Ignore;
+
+    let mut summary = SubmoduleSummary::default();
+    if limit == 0 {
+        return summary;
+    }
+    let Ok(Some(submodules)) = repo.submodules() else {
+        return summary;
+    };
+    for (index, submodule) in submodules.enumerate() {
+        if index == limit {
+            summary.truncated = true;
+            break;
+        }
+        let ignore = submodule.ignore().ok().flatten().unwrap_or_default();
+        if ignore == Ignore::All {
+            continue;
+        }
+        let Ok(status) = submodule.status(ignore, true) else {
+            continue;
+        };
+        if !status.state.repository_exists || !status.state.worktree_checkout {
+            summary.uninitialized += 1;
+        } else if status.checked_out_head_id != status.index_id {
+            summary.out_of_sync += 1;
+        } else if status.is_dirty() == Some(true) {
+            summary.dirty += 1;
+        }
+    }
+    summary
+}
+
+fn get_head_commit_time(repo: &gix::Repository) -> Option<SystemTime> {
+    let time = repo.head_commit().ok()?.time().ok()?;
+    let secs = u64::try_from(time.seconds).ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Modification time of `FETCH_HEAD`, i.e. when the last fetch happened.
+fn get_last_fetch_time(repo: &gix::Repository) -> Option<SystemTime> {
+    [repo.git_dir(), repo.common_dir()]
+        .iter()
+        .find_map(|dir| std::fs::metadata(dir.join("FETCH_HEAD")).ok())?
+        .modified()
+        .ok()
+}
+
+/// Fetch URLs of all remotes, normalized as `host/path`.
+fn get_remote_urls(repo: &gix::Repository) -> Vec<String> {
+    repo.remote_names()
+        .iter()
+        .filter_map(|name| repo.find_remote(name.as_ref()).ok())
+        .filter_map(|remote| {
+            let url = remote.url(gix::remote::Direction::Fetch)?;
+            Some(normalize_remote_url(url.host(), &url.path.to_string()))
+        })
+        .collect()
+}
+
+/// Per-repository settings from the `pulse.*` keys of the git config.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct RepoSettings {
+    /// `pulse.displayName`: replaces the repository name.
+    display_name: Option<String>,
+    /// `pulse.color`: color of the git block.
+    color: Option<Clrs>,
+    /// `pulse.hideEmail`: leaves out `user.email` and the identity check.
+    hide_email: bool,
+    /// `pulse.disableStatus`: skips the working-tree and submodule status.
+    disable_status: bool,
+    /// `pulse.defaultBranch`: branch the default branch distance is
+    /// measured against.
+    default_branch: Option<String>,
+}
+
+impl RepoSettings {
+    fn from_repo(repo: &gix::Repository) -> Self {
+        let config = repo.config_snapshot();
+        let string = |key: &str| config.string(key).map(|value| value.to_string());
+        Self {
+            display_name: string("pulse.displayName"),
+            color: string("pulse.color").and_then(|color| color.parse().ok()),
+            hide_email: config.boolean("pulse.hideEmail").unwrap_or(false),
+            disable_status: config.boolean("pulse.disableStatus").unwrap_or(false),
+            default_branch: string("pulse.defaultBranch"),
+        }
+    }
+}
+
+/// A part of the git info that can take long on large repositories, deep
+/// history or slow disks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SlowIndicator {
+    /// Repository name from the configured strategies, with the
+    /// superproject of a submodule.
+    RepoName(String),
+    /// Branch, or the description of a detached HEAD.
+    Branch(String),
+    Stash(usize),
+    Upstream(Option<UpstreamStatus>),
+    DefaultBranch(Option<DefaultBranchDistance>),
+    Status(Option<GitStatus>),
+    Submodules(SubmoduleSummary),
+    IgnoredDir(bool),
+}
+
+impl SlowIndicator {
+    fn apply(self, info: &mut GitInfo) {
+        let indicators = &mut info.indicators;
+        match self {
+            SlowIndicator::RepoName(repo_name) => info.repo_name = repo_name,
+            SlowIndicator::Branch(branch) => info.branch = branch,
+            SlowIndicator::Stash(stash) => indicators.stash = stash,
+            SlowIndicator::Upstream(upstream) => indicators.upstream = upstream,
+            SlowIndicator::DefaultBranch(distance) => indicators.default_branch = distance,
+            SlowIndicator::Status(status) => indicators.status = status,
+            SlowIndicator::Submodules(submodules) => indicators.submodules = submodules,
+            SlowIndicator::IgnoredDir(ignored_dir) => indicators.ignored_dir = ignored_dir,
+        }
+    }
+}
+
+/// Work that computes the slow indicators of a repository.
+type SlowIndicatorJob = Box<dyn FnOnce(&gix::Repository, &mut dyn FnMut(SlowIndicator)) + Send>;
+
+/// Compute the slow indicators of `repo`, passing each one to `send` as soon
+/// as it is ready.
+///
+/// The repository name is resolved unless `pulse.displayName` replaces it,
+/// without the `origin` URL of an untrusted repository, and HEAD is described
+/// only with `describe_head`.
+fn slow_indicator_job(
+    work_dir: PathBuf,
+    linked_worktree: bool,
+    describe_head: bool,
+    trusted: bool,
+    settings: &RepoSettings,
+    git_config: &GitConfig,
+) -> SlowIndicatorJob {
+    let repo_name_strategies = settings.display_name.is_none().then(|| {
+        let mut strategies = git_config.repo_name_strategies();
+        if !trusted {
+            strategies.retain(|strategy| *strategy != RepoNameStrategy::RemoteUrl);
+        }
+        strategies
+    });
+    let detached_head_strategies = describe_head.then(|| git_config.detached_head_strategies());
+    let git_config = git_config.clone();
+    let default_branch = git_config
+        .default_branch_distance()
+        .then(|| settings.default_branch.clone());
+    let with_status = !settings.disable_status;
+    let submodule_limit = git_config.submodule_limit();
+    Box::new(move |repo, send| {
+        if let Some(strategies) = repo_name_strategies
+            && let Some(repo_name) = resolve_qualified_repo_name(
+                repo,
+                &work_dir,
+                linked_worktree,
+                &strategies,
+                &git_config,
+            )
+        {
+            send(SlowIndicator::RepoName(repo_name));
+        }
+        if let Some(strategies) = detached_head_strategies
+            && let Some(branch) = get_git_branch_from_repo(repo, &strategies)
+        {
+            send(SlowIndicator::Branch(branch));
+        }
+        send(SlowIndicator::Stash(get_stash_count_from_repo(repo)));
+        send(SlowIndicator::Upstream(get_upstream_status_from_repo(repo)));
+        if let Some(name) = default_branch {
+            send(SlowIndicator::DefaultBranch(get_default_branch_distance(
+                repo,
+                name.as_deref(),
+            )));
+        }
+        if with_status {
+            send(SlowIndicator::Status(get_git_status_from_repo(repo)));
+            send(SlowIndicator::Submodules(get_submodule_summary(
+                repo,
+                submodule_limit,
+            )));
+        }
+        let ignored_dir = std::env::current_dir()
+            .and_then(std::fs::canonicalize)
+            .is_ok_and(|dir| is_ignored_dir(repo, &work_dir, &dir));
+        send(SlowIndicator::IgnoredDir(ignored_dir));
+    })
+}
+
+/// Slow indicators, either computed when collected or running on a worker
+/// thread against a deadline.
+enum SlowIndicators {
+    Inline(SlowIndicatorJob),
+    Worker {
+        receiver: mpsc::Receiver<SlowIndicator>,
+        deadline: Instant,
+    },
+}
+
+impl SlowIndicators {
+    fn new(repo: &gix::Repository, job: SlowIndicatorJob, deadline: Option<Instant>) -> Self {
+        let Some(deadline) = deadline else {
+            return SlowIndicators::Inline(job);
+        };
+        let (sender, receiver) = mpsc::channel();
+        let repo = repo.clone();
+        std::thread::spawn(move || {
+            job(&repo, &mut |indicator| {
+                // The receiver is gone once the deadline has passed.
+                let _ = sender.send(indicator);
+            })
+        });
+        SlowIndicators::Worker { receiver, deadline }
+    }
+
+    /// Apply the indicators that finish before the deadline. Returns whether
+    /// all of them did; a late worker is abandoned.
+    fn collect(self, repo: &gix::Repository, info: &mut GitInfo) -> bool {
+        let (receiver, deadline) = match self {
+            SlowIndicators::Inline(job) => {
+                job(repo, &mut |indicator| indicator.apply(info));
+                return true;
+            }
+            SlowIndicators::Worker { receiver, deadline } => (receiver, deadline),
+        };
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(indicator) => indicator.apply(info),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return true,
+                Err(mpsc::RecvTimeoutError::Timeout) => return false,
+            }
+        }
+    }
+}
+
+/// Build the info of a git repository.
+///
+/// With `git.time_budget`, the slow indicators run on a worker thread while
+/// the cheap parts are read, and whatever is not ready when the budget runs
+/// out is left out and marked as partial. A late repository name falls back
+/// to the directory name, and a late description of a detached HEAD to its
+/// short hash.
+fn build_git_info(repo: &gix::Repository, git_config: &GitConfig) -> Option<GitInfo> {
+    build_git_info_until(repo, git_config, budget_deadline(git_config), None)
+}
+
+/// When the time budget of a prompt started now runs out.
+fn budget_deadline(git_config: &GitConfig) -> Option<Instant> {
+    git_config
+        .time_budget()
+        .map(|budget| Instant::now() + budget)
+}
+
+/// Build the info of a git repository against `deadline`. A `branch`
+/// supplied by the caller replaces the one read from HEAD, which is then
+/// neither read nor described.
+fn build_git_info_until(
+    repo: &gix::Repository,
+    git_config: &GitConfig,
+    deadline: Option<Instant>,
+    branch: Option<String>,
+) -> Option<GitInfo> {
+    let work_dir = repo.work_dir()?;
+    let work_dir = std::fs::canonicalize(work_dir).ok()?;
+    let trusted = !git_config.safe_mode() || repo.git_dir_trust() == Trust::Full;
+    let settings = match trusted {
+        true => RepoSettings::from_repo(repo),
+        false => RepoSettings::default(),
+    };
+    let linked_worktree = is_linked_worktree(repo);
+    let operation = get_git_operation_from_repo(repo);
+    let branch = branch.or_else(|| {
+        operation
+            .as_ref()
+            .and_then(|operation| operation.head_name.clone())
+    });
+    let job = slow_indicator_job(
+        work_dir.clone(),
+        linked_worktree,
+        branch.is_none(),
+        trusted,
+        &settings,
+        git_config,
+    );
+    let slow_indicators = SlowIndicators::new(repo, job, deadline);
+
+    // Stand-ins for the repository name and a detached HEAD's description,
+    // kept if the slow indicators miss the deadline.
+    let repo_name = match &settings.display_name {
+        Some(name) => name.clone(),
+        None => resolve_repo_name(
+            repo,
+            &work_dir,
+            linked_worktree,
+            &[RepoNameStrategy::Directory],
+        )?,
+    };
+    let branch = branch
+        .or_else(|| get_git_branch_from_repo(repo, &[]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let config = repo.config_snapshot();
+    let user_email = match trusted && !settings.hide_email {
+        true => config.string("user.email").map(|s| s.to_string()),
+        false => None,
+    };
+    let identity = if !trusted {
+        IdentityStatus::Untrusted
+    } else if settings.hide_email {
+        IdentityStatus::Ok
+    } else {
+        let identity_rules = git_config.identity.as_deref().unwrap_or_default();
+        let remote_urls = match identity_rules.is_empty() {
+            true => Vec::new(),
+            false => get_remote_urls(repo),
+        };
+        check_identity(
+            user_email.as_deref(),
+            &work_dir,
+            &remote_urls,
+            identity_rules,
+        )
+    };
+    let indicators = GitIndicators {
+        operation,
+        linked_worktree,
+        shallow: repo.is_shallow(),
+        // Both are read from the repository's config.
+        sparse_checkout: trusted && is_sparse_checkout(repo),
+        partial_clone: trusted && is_partial_clone(repo),
+        identity,
+        ..Default::default()
+    };
+    let mut info = GitInfo {
+        repo_name,
+        branch,
+        user_email,
+        indicators,
+        head_commit_time: get_head_commit_time(repo),
+        last_fetch_time: get_last_fetch_time(repo),
+        color: settings.color,
+        work_dir,
+    };
+    info.indicators.partial = !slow_indicators.collect(repo, &mut info);
+    Some(info)
+}
+
+fn build_hg_info(repo: &HgRepository, git_config: &GitConfig) -> Option<GitInfo> {
+    let work_dir = std::fs::canonicalize(repo.root()).ok()?;
+    let repo_name = work_dir.file_name()?.to_str()?.to_string();
+    // Like Mercurial, ignore the `.hg/hgrc` of a repository owned by someone
+    // else.
+    let trusted = !git_config.safe_mode() || trust::is_owned_by_current_user(&repo.dot_hg());
+    let user_email = trusted.then(|| repo.user_email()).flatten();
+    let identity = match trusted {
+        true => {
+            let identity_rules = git_config.identity.as_deref().unwrap_or_default();
+            check_identity(user_email.as_deref(), &work_dir, &[], identity_rules)
+        }
+        false => IdentityStatus::Untrusted,
+    };
+    let ignored_dir = std::env::current_dir()
+        .and_then(std::fs::canonicalize)
+        .is_ok_and(|dir| dir.starts_with(work_dir.join(".hg")));
+    let indicators = GitIndicators {
+        identity,
+        ignored_dir,
+        ..Default::default()
+    };
+
+    Some(GitInfo {
+        repo_name,
+        branch: repo.branch_label(),
+        user_email,
+        indicators,
+        head_commit_time: None,
+        last_fetch_time: None,
+        color: None,
+        work_dir,
+    })
+}
+
+/// Build the info of a git repository from its branch alone.
+fn build_git_head_info(head: &GitHead) -> Option<GitInfo> {
+    Some(GitInfo {
+        repo_name: head.root().file_name()?.to_str()?.to_string(),
+        branch: head.branch().to_string(),
+        user_email: None,
+        work_dir: head.root().to_path_buf(),
+        indicators: GitIndicators::default(),
+        head_commit_time: None,
+        last_fetch_time: None,
+        color: None,
+    })
+}
+
+/// Build the info of a jj workspace. The working-copy change replaces the
+/// detached HEAD jj leaves behind in a colocated git repository, whose other
+/// indicators are kept.
+///
+/// `jj log` counts against `git.time_budget`; when it misses the budget,
+/// the git branch stands in for the change and the info is marked partial.
+fn build_jj_info(
+    workspace: &JjWorkspace,
+    git: Option<&gix::Repository>,
+    git_config: &GitConfig,
+) -> Option<GitInfo> {
+    let deadline = budget_deadline(git_config);
+    let timeout = deadline.map_or(JJ_TIMEOUT, |deadline| {
+        deadline.saturating_duration_since(Instant::now())
+    });
+    let working_copy = workspace.working_copy(timeout);
+    let branch = working_copy.as_ref().map(JjWorkingCopy::label);
+    let mut info = match git {
+        Some(repo) => build_git_info_until(repo, git_config, deadline, branch)?,
+        None => {
+            let work_dir = std::fs::canonicalize(workspace.root()).ok()?;
+            GitInfo {
+                repo_name: work_dir.file_name()?.to_str()?.to_string(),
+                branch: branch.unwrap_or_else(|| "unknown".to_string()),
+                user_email: None,
+                work_dir,
+                indicators: GitIndicators::default(),
+                head_commit_time: None,
+                last_fetch_time: None,
+                color: None,
+            }
+        }
+    };
+    let Some(working_copy) = working_copy else {
+        info.indicators.partial |= deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        return Some(info);
+    };
+
+    if info.indicators.identity == IdentityStatus::Untrusted
+        || git.is_some_and(|repo| RepoSettings::from_repo(repo).hide_email)
+    {
+        return Some(info);
+    }
+    if working_copy.email.is_some() {
+        info.user_email = working_copy.email;
+    }
+    let identity_rules = git_config.identity.as_deref().unwrap_or_default();
+    let remote_urls = match (identity_rules.is_empty(), git) {
+        (false, Some(repo)) => get_remote_urls(repo),
+        _ => Vec::new(),
+    };
+    info.indicators.identity = check_identity(
+        info.user_email.as_deref(),
+        &info.work_dir,
+        &remote_urls,
+        identity_rules,
+    );
+    Some(info)
+}
+
+/// Truncate git path for display
+pub fn truncate_git_path(parts: &[&str]) -> String {
+    if parts.is_empty() {
+        String::new()
+    } else if parts.len() > TRUNCATION_THRESHOLD {
+        format!("… {}", parts[parts.len() - TRUNCATION_THRESHOLD..].join(" › "))
+    } else {
+        parts.join(" › ")
+    }
+}
+
+/// Truncate non-git path for display
+pub fn truncate_non_git_path(root: &str, parts: &[&str]) -> String {
+    if parts.is_empty() {
+        root.to_string()
+    } else if parts.len() > TRUNCATION_THRESHOLD {
+        format!("{} … {}", root, parts[parts.len() - TRUNCATION_THRESHOLD..].join(" › "))
+    } else {
+        format!("{} {}", root, parts.join(" › "))
+    }
+}
+
+/// Builds the prompt line outside a repository, `user@host:path` with the
+/// default segments.
+///
+/// This helper handles:
+/// - Path normalization (extracting root ~ or / and navigation portion)
+/// - Navigation splitting by '/'
+/// - Truncation of long paths
+/// - Building the colored user@host:path string
+///
+/// # Arguments
+/// * `dir` - The current directory path
+/// * `user` - The username string
+/// * `host` - The hostname string
+/// * `colors` - The PromptColors struct containing color definitions
+/// * `layout` - Order and separators of the segments
+///
+/// # Returns
+/// A formatted string with the configured segments colored
+pub fn build_non_git_path_string(
+    dir: &str,
+    user: &str,
+    host: &str,
+    colors: &PromptColors,
+    layout: &SegmentLayout,
+) -> String {
+    let (root, nav) = if dir == "~" {
+        ("~", "".to_string())
+    } else if dir.starts_with("~/") {
+        (
+            "~",
+            dir.strip_prefix("~/")
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+        )
+    } else {
+        (
+            "/",
+            dir.strip_prefix("/")
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+        )
+    };
+    let nav_parts: Vec<&str> = nav.split('/').filter(|s| !s.is_empty()).collect();
+    let path_display = truncate_non_git_path(root, &nav_parts);
+
+    layout
+        .render(colors, |kind| {
+            let (text, color) = match kind {
+                SegmentKind::Username => (user, colors.user_color),
+                SegmentKind::Hostname => (host, colors.host_color),
+                SegmentKind::CurrentDirectory => (path_display.as_str(), colors.dir_color),
+                SegmentKind::GitBranch | SegmentKind::Command | SegmentKind::Env => {
+                    return None;
+                }
+            };
+            Some(RenderedSegment::painted(text, text.color(color)))
+        })
+        .text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    struct DirGuard {
+        original: PathBuf,
+    }
+
+    impl DirGuard {
+        fn new(target: &std::path::Path) -> Self {
+            let original = std::env::current_dir().expect("current dir should be available");
+            std::env::set_current_dir(target).expect("set current dir to temp repo");
+            Self { original }
+        }
+    }
+
+    impl Drop for DirGuard {
+        fn drop(&mut self) {
+            let _ = std::env::set_current_dir(&self.original);
+        }
+    }
+
+    fn init_temp_git_repo() -> TempDir {
+        let temp_dir = tempfile::tempdir().expect("create temp dir");
+        let _repo = gix::init(temp_dir.path()).expect("init temp git repo");
+        temp_dir
+    }
+
+    /// Commit `files` as a flat tree on top of HEAD and check them out,
+    /// updating the index so the worktree is clean afterwards.
+    fn commit_files(
+        repo: &gix::Repository,
+        files: &[(&str, &str)],
+        message: &str,
+    ) -> gix::ObjectId {
+        use gix::config::tree::{Author, Committer};
+
+        let mut repo = repo.clone();
+        {
+            let mut config = repo.config_snapshot_mut();
+            for (key, value) in [
+                (&Author::NAME, "Pulse"),
+                (&Author::EMAIL, "pulse@example.com"),
+                (&Committer::NAME, "Pulse"),
+                (&Committer::EMAIL, "pulse@example.com"),
+            ] {
+                config.set_value(key, value).expect("set identity");
+            }
+        }
+        let tree_id = stage_files(&repo, files);
+        let parents = repo.head_id().ok().map(|id| id.detach());
+        repo.commit("HEAD", message, tree_id, parents)
+            .expect("commit")
+            .detach()
+    }
+
+    /// Replace the index with a flat tree of `files` and write them to the
+    /// worktree, returning the id of that tree.
+    fn stage_files(repo: &gix::Repository, files: &[(&str, &str)]) -> gix::ObjectId {
+        let mut entries = files
+            .iter()
+            .map(|(name, content)| gix::objs::tree::Entry {
+                mode: gix::objs::tree::EntryKind::Blob.into(),
+                filename: (*name).into(),
+                oid: repo
+                    .write_blob(content.as_bytes())
+                    .expect("write blob")
+                    .detach(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.filename.cmp(&b.filename));
+        let tree_id = repo
+            .write_object(&gix::objs::Tree { entries })
+            .expect("write tree")
+            .detach();
+
+        let state = gix::index::State::from_tree(&tree_id, &repo.objects).expect("index from tree");
+        let mut index = gix::index::File::from_state(state, repo.index_path());
+        index.write(Default::default()).expect("write index");
+        let work_dir = repo.work_dir().expect("work dir");
+        for (name, content) in files {
+            std::fs::write(work_dir.join(name), content).expect("write worktree file");
+        }
+        tree_id
+    }
+
+    fn write_ref(repo: &gix::Repository, name: &str, id: gix::ObjectId) {
+        let path = repo.git_dir().join(name);
+        std::fs::create_dir_all(path.parent().expect("ref parent")).expect("create ref dir");
+        std::fs::write(path, format!("{}\n", id)).expect("write ref");
+    }
+
+    fn append_git_config(repo: &gix::Repository, content: &str) {
+        let path = repo.git_dir().join("config");
+        let mut config = std::fs::read_to_string(&path).expect("read git config");
+        config.push_str(content);
+        std::fs::write(path, config).expect("write git config");
+    }
+
+    const ORIGIN_TRACKING_CONFIG: &str = "[remote \"origin\"]\n\
+        \turl = https://example.com/pulse.git\n\
+        \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+        [branch \"main\"]\n\
+        \tremote = origin\n\
+        \tmerge = refs/heads/main\n";
+
+    fn repo_name_from_path(path: &std::path::Path) -> String {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .expect("temp repo dir name")
+            .to_string()
+    }
+
+    #[test]
+    #[serial]
+    fn test_is_in_git_repo() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path());
+        assert!(repo.is_some());
+    }
+
+    #[test]
+    fn test_get_username() {
+        let username = get_username();
+        assert!(username.is_ok());
+        let uname = username.expect("username should be Ok after is_ok check");
+        assert!(!uname.is_empty());
+    }
+
+    #[test]
+    fn test_get_current_directory() {
+        let cwd = get_current_directory();
+        assert!(cwd.is_ok());
+        let cwd_str = cwd.expect("cwd should be Ok after is_ok check");
+        assert!(!cwd_str.is_empty());
+        // Should start with / or ~
+        assert!(cwd_str.starts_with('/') || cwd_str.starts_with('~'));
+    }
+
+    #[test]
+    fn test_get_hostname() {
+        let hostname = get_hostname();
+        assert!(hostname.is_ok());
+        let hname = hostname.expect("hostname should be Ok after is_ok check");
+        assert!(!hname.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_git_branch() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let branch = get_git_branch_from_repo(&repo, &DetachedHeadStrategy::DEFAULT_ORDER);
+        assert!(branch.is_some());
+        let branch_name = branch.expect("branch should be Some after is_some check");
+        assert!(!branch_name.is_empty());
+    }
+
+    fn detach_head(repo: &gix::Repository, id: gix::ObjectId) {
+        std::fs::write(repo.git_dir().join("HEAD"), format!("{}\n", id)).expect("detach");
+    }
+
+    #[test]
+    #[serial]
+    fn test_detached_head_exact_tag() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let commit = commit_files(&repo, &[("a.txt", "a")], "release");
+        write_ref(&repo, "refs/tags/v1.4.2", commit);
+        detach_head(&repo, commit);
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let branch = get_git_branch_from_repo(&repo, &DetachedHeadStrategy::DEFAULT_ORDER);
+        assert_eq!(branch.as_deref(), Some("v1.4.2"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_detached_head_nearest_tag_with_distance() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let tagged = commit_files(&repo, &[("a.txt", "a")], "release");
+        write_ref(&repo, "refs/tags/v1.4.2", tagged);
+        commit_files(&repo, &[("a.txt", "b")], "fix");
+        let head = commit_files(&repo, &[("a.txt", "c")], "fix again");
+        detach_head(&repo, head);
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let branch = get_git_branch_from_repo(&repo, &DetachedHeadStrategy::DEFAULT_ORDER);
+        assert_eq!(branch.as_deref(), Some("v1.4.2+2"));
+
+        let branch = get_git_branch_from_repo(&repo, &[DetachedHeadStrategy::ExactTag]);
+        assert_eq!(branch, Some(head.to_hex_with_len(7).to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_detached_head_remote_branch_and_strategy_order() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let commit = commit_files(&repo, &[("a.txt", "a")], "initial");
+        write_ref(&repo, "refs/remotes/origin/main", commit);
+        detach_head(&repo, commit);
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let branch = get_git_branch_from_repo(&repo, &DetachedHeadStrategy::DEFAULT_ORDER);
+        assert_eq!(branch.as_deref(), Some("origin/main"));
+
+        write_ref(&repo, "refs/tags/v1.0.0", commit);
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let branch = get_git_branch_from_repo(
+            &repo,
+            &[
+                DetachedHeadStrategy::RemoteBranch,
+                DetachedHeadStrategy::ExactTag,
+            ],
+        );
+        assert_eq!(branch.as_deref(), Some("origin/main"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_detached_head_falls_back_to_short_hash() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let commit = commit_files(&repo, &[("a.txt", "a")], "initial");
+        detach_head(&repo, commit);
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let branch = get_git_branch_from_repo(&repo, &DetachedHeadStrategy::DEFAULT_ORDER);
+        assert_eq!(branch, Some(commit.to_hex_with_len(7).to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_git_info() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let expected = repo_name_from_path(temp_dir.path());
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.repo_name, expected);
+        assert!(!info.branch.is_empty());
+        assert!(info.work_dir.is_absolute());
+    }
+
+    #[test]
+    fn test_repo_name_from_url_path() {
+        for (path, expected) in [
+            ("/owner/repo.git", "owner/repo"),
+            ("owner/repo.git", "owner/repo"),
+            ("/group/sub/repo/", "sub/repo"),
+            ("/repo", "repo"),
+        ] {
+            assert_eq!(repo_name_from_url_path(path).as_deref(), Some(expected));
+        }
+        assert_eq!(repo_name_from_url_path("/"), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_repo_name_strategies() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        append_git_config(
+            &repo,
+            "[remote \"origin\"]\n\turl = git@github.com:ourcorp/pulse.git\n",
+        );
+        std::fs::write(
+            temp_dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .expect("write workspace manifest");
+        let member_dir = temp_dir.path().join("crates").join("core");
+        std::fs::create_dir_all(member_dir.join("src")).expect("create member");
+        std::fs::write(
+            member_dir.join("Cargo.toml"),
+            "[package]\nname = \"core\"\n",
+        )
+        .expect("write member manifest");
+        let guard = DirGuard::new(&member_dir.join("src"));
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let name_with = |strategies: Vec<&str>| {
+            let git_config = GitConfig {
+                repo_name: Some(strategies.into_iter().map(str::to_string).collect()),
+                ..Default::default()
+            };
+            build_git_info(&repo, &git_config)
+                .expect("build git info")
+                .repo_name
+        };
+
+        let directory_name = repo_name_from_path(temp_dir.path());
+        assert_eq!(name_with(vec!["directory"]), directory_name);
+        assert_eq!(name_with(vec!["remote_url"]), "ourcorp/pulse");
+        assert_eq!(name_with(vec!["workspace_member", "remote_url"]), "core");
+
+        drop(guard);
+        let _guard = DirGuard::new(temp_dir.path());
+        assert_eq!(
+            name_with(vec!["workspace_member", "remote_url"]),
+            "ourcorp/pulse"
+        );
+        assert_eq!(name_with(vec!["workspace_member"]), directory_name);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_git_status_clean_repo() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let status = get_git_status_from_repo(&repo).expect("status");
+        assert!(!status.is_dirty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_git_status_counts_untracked() {
+        let temp_dir = init_temp_git_repo();
+        std::fs::write(temp_dir.path().join("new.txt"), "hello").expect("write file");
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        let status = info.indicators.status.expect("status");
+        assert_eq!(status.untracked, 1);
+        assert_eq!(status.staged, 0);
+        assert!(status.is_dirty());
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_git_status_skips_empty_directories() {
+        let temp_dir = init_temp_git_repo();
+        std::fs::create_dir_all(temp_dir.path().join("e1")).expect("create e1");
+        std::fs::create_dir_all(temp_dir.path().join("e2/x")).expect("create e2/x");
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let status = get_git_status_from_repo(&repo).expect("status");
+        assert_eq!(status.untracked, 0);
+
+        commit_files(&repo, &[("a.txt", "a")], "initial");
+        std::fs::create_dir_all(temp_dir.path().join("e3/y/z")).expect("create e3/y/z");
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let status = get_git_status_from_repo(&repo).expect("status");
+        assert_eq!(status.untracked, 0);
+
+        std::fs::write(temp_dir.path().join("e2/x/new.txt"), "new").expect("write file");
+        let status = get_git_status_from_repo(&repo).expect("status");
+        assert_eq!(status.untracked, 1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_git_status_counts_worktree_and_staged_changes() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        commit_files(&repo, &[("a.txt", "a"), ("b.txt", "b")], "initial");
+        std::fs::write(temp_dir.path().join("a.txt"), "changed").expect("modify file");
+        std::fs::remove_file(temp_dir.path().join("b.txt")).expect("delete file");
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let status = get_git_status_from_repo(&repo).expect("status");
+        assert_eq!(status.modified, 1);
+        assert_eq!(status.deleted, 1);
+        assert_eq!(status.staged, 0);
+        assert_eq!(status.untracked, 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_git_status_counts_staged_additions_and_renames() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        commit_files(&repo, &[("a.txt", "a"), ("b.txt", "b")], "initial");
+        stage_files(&repo, &[("a.txt", "a"), ("c.txt", "c"), ("moved.txt", "b")]);
+        std::fs::remove_file(temp_dir.path().join("b.txt")).expect("delete file");
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let status = get_git_status_from_repo(&repo).expect("status");
+        assert_eq!(status.staged, 1);
+        assert_eq!(status.renamed, 1);
+        assert_eq!(status.deleted, 0);
+        assert_eq!(status.untracked, 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_git_operation_interactive_rebase() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let commit = commit_files(&repo, &[("a.txt", "a")], "initial");
+        std::fs::write(repo.git_dir().join("HEAD"), format!("{}\n", commit)).expect("detach");
+        let state_dir = repo.git_dir().join("rebase-merge");
+        std::fs::create_dir_all(&state_dir).expect("create rebase-merge");
+        std::fs::write(state_dir.join("interactive"), "").expect("write interactive");
+        std::fs::write(state_dir.join("msgnum"), "3\n").expect("write msgnum");
+        std::fs::write(state_dir.join("end"), "7\n").expect("write end");
+        std::fs::write(state_dir.join("head-name"), "refs/heads/feature\n")
+            .expect("write head-name");
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.branch, "feature");
+        assert_eq!(
+            info.indicators.operation,
+            Some(GitOperation {
+                label: "REBASE-i",
+                progress: Some((3, 7)),
+                head_name: Some("feature".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_git_operation_merge() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let commit = commit_files(&repo, &[("a.txt", "a")], "initial");
+        std::fs::write(repo.git_dir().join("MERGE_HEAD"), format!("{}\n", commit))
+            .expect("write MERGE_HEAD");
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let operation = get_git_operation_from_repo(&repo).expect("operation");
+        assert_eq!(operation.label, "MERGING");
+        assert_eq!(operation.progress, None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_git_operation_none_for_clean_repo() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        assert_eq!(get_git_operation_from_repo(&repo), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_default_branch_distance() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        std::fs::write(repo.git_dir().join("HEAD"), "ref: refs/heads/feature\n")
+            .expect("write HEAD");
+        let base = commit_files(&repo, &[("a.txt", "a")], "base");
+        let first = commit_files(&repo, &[("a.txt", "b")], "first");
+        let second = commit_files(&repo, &[("a.txt", "c")], "second");
+        write_ref(&repo, "refs/heads/main", base);
+        let distance = |name: &str, ahead, behind| {
+            Some(DefaultBranchDistance {
+                name: name.to_string(),
+                ahead,
+                behind,
+            })
+        };
+
+        assert_eq!(
+            get_default_branch_distance(&repo, None),
+            distance("main", 2, 0)
+        );
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.indicators.default_branch, None);
+        let git_config = GitConfig {
+            default_branch_distance: Some(true),
+            ..Default::default()
+        };
+        let info = build_git_info(&repo, &git_config).expect("build git info");
+        assert_eq!(info.indicators.default_branch, distance("main", 2, 0));
+
+        write_ref(&repo, "refs/remotes/origin/trunk", second);
+        std::fs::write(
+            repo.git_dir().join("refs/remotes/origin/HEAD"),
+            "ref: refs/remotes/origin/trunk\n",
+        )
+        .expect("write origin/HEAD");
+        assert_eq!(get_default_branch_distance(&repo, None), None);
+
+        write_ref(&repo, "refs/heads/feature", first);
+        assert_eq!(
+            get_default_branch_distance(&repo, None),
+            distance("trunk", 0, 1)
+        );
+
+        std::fs::write(repo.git_dir().join("HEAD"), "ref: refs/heads/trunk\n").expect("write HEAD");
+        write_ref(&repo, "refs/heads/trunk", base);
+        assert_eq!(get_default_branch_distance(&repo, None), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_repo_settings_from_git_config() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let base = commit_files(&repo, &[("a.txt", "a")], "base");
+        commit_files(&repo, &[("a.txt", "b")], "feature");
+        write_ref(&repo, "refs/heads/develop", base);
+        std::fs::write(temp_dir.path().join("untracked.txt"), "").expect("write file");
+        append_git_config(&repo, "[user]\n\temail = me@example.com\n");
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let git_config = GitConfig {
+            default_branch_distance: Some(true),
+            ..Default::default()
+        };
+
+        let info = build_git_info(&repo, &git_config).expect("build git info");
+        assert_eq!(info.user_email.as_deref(), Some("me@example.com"));
+        assert!(info.indicators.status.is_some());
+        assert_eq!(info.color, None);
+
+        append_git_config(
+            &repo,
+            "[pulse]\n\tdisplayName = API\n\tcolor = Red\n\thideEmail = true\n\
+             \tdisableStatus = true\n\tdefaultBranch = develop\n",
+        );
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &git_config).expect("build git info");
+        assert_eq!(info.repo_name, "API");
+        assert_eq!(info.color, Some(Clrs::Red));
+        assert_eq!(info.user_email, None);
+        assert_eq!(info.indicators.identity, IdentityStatus::Ok);
+        assert_eq!(info.indicators.status, None);
+        assert_eq!(
+            info.indicators.default_branch,
+            Some(DefaultBranchDistance {
+                name: "develop".to_string(),
+                ahead: 1,
+                behind: 0,
+            })
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_repo_settings_from_included_config() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let include = temp_dir.path().join("pulse.gitconfig");
+        std::fs::write(&include, "[pulse]\n\tdisplayName = Included\n").expect("write include");
+        append_git_config(
+            &repo,
+            &format!("[include]\n\tpath = {}\n", include.display()),
+        );
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.repo_name, "Included");
+    }
+
+    #[test]
+    #[serial]
+    fn test_repository_owned_by_other_user_is_untrusted() {
+        let temp_dir = init_temp_git_repo();
+        let root = std::fs::canonicalize(temp_dir.path()).expect("canonicalize");
+        let repo = discover_git_repo_in(&root).expect("repo");
+        append_git_config(
+            &repo,
+            "[user]\n\temail = them@example.com\n[pulse]\n\tdisplayName = Theirs\n",
+        );
+        // Only root can hand the repository to another user.
+        if std::os::unix::fs::chown(repo.git_dir(), Some(4242), None).is_err() {
+            return;
+        }
+
+        let repo = discover_git_repo_in(&root).expect("repo");
+        assert_eq!(repo.git_dir_trust(), Trust::Reduced);
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.user_email, None);
+        assert_eq!(info.indicators.identity, IdentityStatus::Untrusted);
+        assert_ne!(info.repo_name, "Theirs");
+
+        let relaxed = GitConfig {
+            safe_mode: Some(false),
+            ..Default::default()
+        };
+        let info = build_git_info(&repo, &relaxed).expect("build git info");
+        assert_eq!(info.user_email.as_deref(), Some("them@example.com"));
+        assert_eq!(info.repo_name, "Theirs");
+
+        // Nothing else is taken from the local config of an untrusted
+        // repository either.
+        append_git_config(
+            &repo,
+            "[core]\n\tsparseCheckout = true\n\
+             [remote \"origin\"]\n\turl = https://example.com/ourcorp/pulse.git\n\
+             \tpromisor = true\n",
+        );
+        let info_dir = repo.git_dir().join("info");
+        std::fs::create_dir_all(&info_dir).expect("create info dir");
+        std::fs::write(info_dir.join("sparse-checkout"), "/*\n").expect("write sparse-checkout");
+        let remote_url = GitConfig {
+            repo_name: Some(vec!["remote_url".to_string()]),
+            ..Default::default()
+        };
+        let repo = discover_git_repo_in(&root).expect("repo");
+        let info = build_git_info(&repo, &remote_url).expect("build git info");
+        assert!(!info.indicators.sparse_checkout);
+        assert!(!info.indicators.partial_clone);
+        assert_eq!(
+            Some(info.repo_name.as_str()),
+            root.file_name().and_then(|name| name.to_str())
+        );
+        let info = build_git_info(&repo, &relaxed).expect("build git info");
+        assert!(info.indicators.sparse_checkout);
+
+        let home = TempDir::new().expect("temp home");
+        std::fs::write(
+            home.path().join(".gitconfig"),
+            format!("[safe]\n\tdirectory = {}\n", root.display()),
+        )
+        .expect("write global config");
+        let previous_home = std::env::var_os("HOME");
+        unsafe { std::env::set_var("HOME", home.path()) };
+        let repo = discover_git_repo_in(&root);
+        match previous_home {
+            Some(previous_home) => unsafe { std::env::set_var("HOME", previous_home) },
+            None => unsafe { std::env::remove_var("HOME") },
+        }
+        assert_eq!(repo.expect("repo").git_dir_trust(), Trust::Full);
+    }
+
+    #[test]
+    #[serial]
+    fn test_slow_indicators_stop_at_deadline() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let job = || -> SlowIndicatorJob {
+            Box::new(|_, send| {
+                send(SlowIndicator::Upstream(Some(UpstreamStatus::NoUpstream)));
+                std::thread::sleep(Duration::from_millis(300));
+                send(SlowIndicator::IgnoredDir(true));
+            })
+        };
+
+        let empty_info = || GitInfo {
+            repo_name: "repo".to_string(),
+            branch: "main".to_string(),
+            user_email: None,
+            work_dir: temp_dir.path().to_path_buf(),
+            indicators: GitIndicators::default(),
+            head_commit_time: None,
+            last_fetch_time: None,
+            color: None,
+        };
+
+        let mut info = empty_info();
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50);
+        assert!(!SlowIndicators::new(&repo, job(), Some(deadline)).collect(&repo, &mut info));
+        assert!(start.elapsed() < Duration::from_millis(250));
+        assert_eq!(info.indicators.upstream, Some(UpstreamStatus::NoUpstream));
+        assert!(!info.indicators.ignored_dir);
+
+        let mut info = empty_info();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert!(SlowIndicators::new(&repo, job(), Some(deadline)).collect(&repo, &mut info));
+        assert!(info.indicators.ignored_dir);
+
+        let mut info = empty_info();
+        assert!(SlowIndicators::new(&repo, job(), None).collect(&repo, &mut info));
+        assert!(info.indicators.ignored_dir);
+    }
+
+    #[test]
+    #[serial]
+    fn test_stash_count_from_reflog() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let base = commit_files(&repo, &[("a.txt", "a")], "initial");
+        assert_eq!(get_stash_count_from_repo(&repo), 0);
+
+        let first = commit_files(&repo, &[("a.txt", "b")], "WIP on main: first");
+        let second = commit_files(&repo, &[("a.txt", "c")], "WIP on main: second");
+        write_ref(&repo, "refs/stash", second);
+        let null = gix::ObjectId::null(gix::hash::Kind::Sha1);
+        let log = format!(
+            "{null} {first} Pulse <pulse@example.com> 1700000000 +0000\tWIP on main: {base}\n\
+             {first} {second} Pulse <pulse@example.com> 1700000100 +0000\tWIP on main: {base}\n"
+        );
+        let log_path = repo.git_dir().join("logs").join("refs").join("stash");
+        std::fs::create_dir_all(log_path.parent().expect("log parent")).expect("create logs");
+        std::fs::write(log_path, log).expect("write stash reflog");
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.indicators.stash, 2);
+    }
+
+    #[test]
+    #[serial]
+    fn test_linked_worktree_uses_main_repo_name() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let commit = commit_files(&repo, &[("a.txt", "a")], "initial");
+        write_ref(&repo, "refs/heads/feature", commit);
+
+        let worktree_dir = tempfile::tempdir().expect("create worktree dir");
+        let admin_dir = repo.git_dir().join("worktrees").join("feature");
+        std::fs::create_dir_all(&admin_dir).expect("create worktree admin dir");
+        std::fs::write(admin_dir.join("HEAD"), "ref: refs/heads/feature\n").expect("write HEAD");
+        std::fs::write(admin_dir.join("commondir"), "../..\n").expect("write commondir");
+        let dot_git = worktree_dir.path().join(".git");
+        std::fs::write(admin_dir.join("gitdir"), format!("{}\n", dot_git.display()))
+            .expect("write gitdir");
+        std::fs::write(&dot_git, format!("gitdir: {}\n", admin_dir.display()))
+            .expect("write .git file");
+
+        let main_info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert!(!main_info.indicators.linked_worktree);
+
+        let repo = discover_git_repo_in(worktree_dir.path()).expect("worktree repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.repo_name, repo_name_from_path(temp_dir.path()));
+        assert_eq!(info.branch, "feature");
+        assert!(info.indicators.linked_worktree);
+        assert_eq!(
+            info.work_dir,
+            std::fs::canonicalize(worktree_dir.path()).expect("canonicalize")
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_shallow_sparse_and_partial_clone() {
+        let indicators = |path: &std::path::Path| {
+            let repo = discover_git_repo_in(path).expect("repo");
+            build_git_info(&repo, &GitConfig::default())
+                .expect("build git info")
+                .indicators
+        };
+
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let commit = commit_files(&repo, &[("a.txt", "a")], "initial");
+        let plain = indicators(temp_dir.path());
+        assert!(!plain.shallow && !plain.sparse_checkout && !plain.partial_clone);
+
+        std::fs::write(repo.git_dir().join("shallow"), format!("{}\n", commit))
+            .expect("write shallow file");
+        assert!(indicators(temp_dir.path()).shallow);
+
+        append_git_config(&repo, "[core]\n\tsparseCheckout = true\n");
+        assert!(!indicators(temp_dir.path()).sparse_checkout);
+        let info_dir = repo.git_dir().join("info");
+        std::fs::create_dir_all(&info_dir).expect("create info dir");
+        std::fs::write(info_dir.join("sparse-checkout"), "/*\n!/docs/\n")
+            .expect("write sparse-checkout");
+        assert!(indicators(temp_dir.path()).sparse_checkout);
+
+        append_git_config(
+            &repo,
+            "[remote \"origin\"]\n\turl = https://example.com/pulse.git\n\
+             \tpromisor = true\n\tpartialclonefilter = blob:none\n",
+        );
+        let partial = indicators(temp_dir.path());
+        assert!(partial.shallow && partial.sparse_checkout && partial.partial_clone);
+    }
+
+    #[test]
+    #[serial]
+    fn test_ignored_dir_and_git_dir() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        commit_files(&repo, &[(".gitignore", "target/\n")], "initial");
+        let ignored_in = |dir: std::path::PathBuf| {
+            std::fs::create_dir_all(&dir).expect("create dir");
+            let _guard = DirGuard::new(&dir);
+            let repo = discover_git_repo_in(".").expect("repo");
+            build_git_info(&repo, &GitConfig::default()).map(|info| info.indicators.ignored_dir)
+        };
+
+        assert_eq!(ignored_in(temp_dir.path().to_path_buf()), Some(false));
+        assert_eq!(ignored_in(temp_dir.path().join("src")), Some(false));
+        assert_eq!(ignored_in(temp_dir.path().join("target/debug")), Some(true));
+        assert_eq!(ignored_in(temp_dir.path().join(".git/refs")), Some(true));
+    }
+
+    #[test]
+    #[serial]
+    fn test_mercurial_repository() {
+        let temp_dir = tempfile::tempdir().expect("create temp dir");
+        let dot_hg = temp_dir.path().join(".hg");
+        std::fs::create_dir(&dot_hg).expect("create .hg");
+        std::fs::write(dot_hg.join("branch"), "default\n").expect("write branch");
+        std::fs::write(dot_hg.join("bookmarks.current"), "feature").expect("write bookmark");
+        std::fs::write(
+            dot_hg.join("hgrc"),
+            "[ui]\nusername = Dev <dev@example.com>\n",
+        )
+        .expect("write hgrc");
+        let src = temp_dir.path().join("src");
+        std::fs::create_dir(&src).expect("create src");
+
+        let vcs = discover_vcs_in(&src, &GitConfig::default()).expect("discover repository");
+        assert!(matches!(vcs, Vcs::Mercurial(_)));
+        let info = LazyGitInfo::new(Some(vcs), GitConfig::default())
+            .get()
+            .cloned()
+            .expect("mercurial info");
+        assert_eq!(info.repo_name, repo_name_from_path(temp_dir.path()));
+        assert_eq!(info.branch, "feature");
+        assert_eq!(info.user_email.as_deref(), Some("dev@example.com"));
+
+        let prompt = PromptBuilder::from_config(&Config {
+            mode: Some("Inline".to_string()),
+            ..Default::default()
+        })
+        .terminal_width(200)
+        .git_info(Some(info.clone()))
+        .current_dir_path(info.work_dir.join("src"))
+        .user("unused")
+        .host("unused")
+        .dir("unused")
+        .render()
+        .expect("prompt render");
+        assert_eq!(
+            strip_ansi(&prompt),
+            format!("dev@example.com: [{} : feature] src $ ", info.repo_name)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_nearest_repository_wins() {
+        let temp_dir = init_temp_git_repo();
+        let hg_dir = temp_dir.path().join("hg");
+        std::fs::create_dir_all(hg_dir.join(".hg")).expect("create .hg");
+        let git_dir = hg_dir.join("vendor");
+        gix::init(&git_dir).expect("init nested git repo");
+
+        let config = GitConfig::default();
+        assert!(matches!(
+            discover_vcs_in(temp_dir.path(), &config),
+            Some(Vcs::Git(_))
+        ));
+        assert!(matches!(
+            discover_vcs_in(&hg_dir, &config),
+            Some(Vcs::Mercurial(_))
+        ));
+        assert!(matches!(
+            discover_vcs_in(&git_dir, &config),
+            Some(Vcs::Git(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_discovery_exclusions_and_ceilings() {
+        let temp_dir = init_temp_git_repo();
+        let root = std::fs::canonicalize(temp_dir.path()).expect("canonicalize");
+        let nested = root.join("build").join("out");
+        std::fs::create_dir_all(&nested).expect("create dirs");
+
+        let mut config = GitConfig::default();
+        config.discovery.exclude = Some(vec![format!("{}/build", root.display())]);
+        assert!(discover_vcs_in(&nested, &config).is_none());
+        assert!(matches!(discover_vcs_in(&root, &config), Some(Vcs::Git(_))));
+
+        let ceilings = [root.join("build")];
+        assert!(discover_nearest_vcs(&nested, &ceilings, true).is_none());
+        assert!(matches!(
+            discover_nearest_vcs(&root.join("build"), &ceilings, true),
+            Some(Vcs::Git(_))
+        ));
+
+        let head = GitHead::discover(&nested, &[]).expect("git head");
+        let info = Vcs::GitHead(head)
+            .build_info(&config)
+            .expect("build git info");
+        assert_eq!(info.branch, "main");
+        assert_eq!(info.work_dir, root);
+        assert_eq!(info.indicators.identity, IdentityStatus::Ok);
+    }
+
+    #[test]
+    #[serial]
+    fn test_jj_workspace_detection() {
+        let temp_dir = init_temp_git_repo();
+        let root = std::fs::canonicalize(temp_dir.path()).expect("canonicalize");
+        std::fs::create_dir(root.join(".jj")).expect("create .jj");
+
+        match discover_vcs_in(&root, &GitConfig::default()) {
+            Some(Vcs::Jujutsu { workspace, git }) => {
+                assert_eq!(workspace.root(), root);
+                assert!(git.is_some());
+            }
+            _ => panic!("expected a colocated jj workspace"),
+        }
+
+        let native = root.join("native");
+        std::fs::create_dir_all(native.join(".jj")).expect("create native .jj");
+        assert!(matches!(
+            discover_vcs_in(&native, &GitConfig::default()),
+            Some(Vcs::Jujutsu { git: None, .. })
+        ));
+
+        let vendor = native.join("vendor");
+        gix::init(&vendor).expect("init nested git repo");
+        assert!(matches!(
+            discover_vcs_in(&vendor, &GitConfig::default()),
+            Some(Vcs::Git(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_jj_log_counts_against_time_budget() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().expect("temp dir");
+        std::fs::create_dir(temp_dir.path().join(".jj")).expect("create .jj");
+        let workspace = JjWorkspace::discover(temp_dir.path(), &[]).expect("workspace");
+        let bin = TempDir::new().expect("temp bin");
+        let jj = bin.path().join("jj");
+        let write_jj = |script: &str| {
+            std::fs::write(&jj, format!("#!/bin/sh\n{}\n", script)).expect("write jj");
+            std::fs::set_permissions(&jj, std::fs::Permissions::from_mode(0o755))
+                .expect("chmod jj");
+        };
+        let budget = GitConfig {
+            time_budget: Some("100ms".to_string()),
+            ..Default::default()
+        };
+
+        let previous_path = std::env::var_os("PATH").unwrap_or_default();
+        let mut path = bin.path().as_os_str().to_owned();
+        path.push(":");
+        path.push(&previous_path);
+        unsafe { std::env::set_var("PATH", path) };
+        write_jj(r"printf 'kntqzsqt\nmain\nme@example.com\n'");
+        let info = build_jj_info(&workspace, None, &budget);
+        write_jj(r"sleep 0.5; printf 'kntqzsqt\nmain\nme@example.com\n'");
+        let start = Instant::now();
+        let late = build_jj_info(&workspace, None, &budget);
+        let elapsed = start.elapsed();
+        unsafe { std::env::set_var("PATH", previous_path) };
+
+        let info = info.expect("jj info");
+        assert_eq!(info.branch, "kntqzsqt main");
+        assert_eq!(info.user_email.as_deref(), Some("me@example.com"));
+        assert!(!info.indicators.partial);
+        let late = late.expect("late jj info");
+        assert!(elapsed < Duration::from_millis(400));
+        assert_eq!(late.branch, "unknown");
+        assert!(late.indicators.partial);
+    }
+
+    /// Create a superproject with a submodule checked out at `lib`, in the
+    /// old form with its own `.git` directory, and an uninitialized one at
+    /// `vendor`. Returns the superproject and the submodule repository.
+    fn init_superproject() -> (TempDir, gix::Repository) {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let lib_dir = temp_dir.path().join("lib");
+        let lib = gix::init(&lib_dir).expect("init submodule");
+        let commit = commit_files(&lib, &[("lib.rs", "")], "initial");
+
+        let gitmodules = "[submodule \"lib\"]\n\tpath = lib\n\turl = https://example.com/lib.git\n\
+            [submodule \"vendor\"]\n\tpath = vendor\n\turl = https://example.com/vendor.git\n";
+        std::fs::write(temp_dir.path().join(".gitmodules"), gitmodules).expect("write .gitmodules");
+        let entries = ["lib", "vendor"]
+            .into_iter()
+            .map(|name| gix::objs::tree::Entry {
+                mode: gix::objs::tree::EntryKind::Commit.into(),
+                filename: name.into(),
+                oid: commit,
+            })
+            .collect();
+        let tree_id = repo
+            .write_object(&gix::objs::Tree { entries })
+            .expect("write tree")
+            .detach();
+        let state = gix::index::State::from_tree(&tree_id, &repo.objects).expect("index from tree");
+        let mut index = gix::index::File::from_state(state, repo.index_path());
+        index.write(Default::default()).expect("write index");
+        (temp_dir, lib)
+    }
+
+    #[test]
+    #[serial]
+    fn test_submodule_summary() {
+        let (temp_dir, lib) = init_superproject();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let summary = |limit| get_submodule_summary(&repo, limit);
+
+        assert_eq!(
+            summary(20),
+            SubmoduleSummary {
+                uninitialized: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(summary(0), SubmoduleSummary::default());
+
+        let lib_dir = lib.work_dir().expect("submodule work dir");
+        std::fs::write(lib_dir.join("untracked.rs"), "").expect("write untracked file");
+        assert_eq!(summary(20).dirty, 1);
+
+        commit_files(&lib, &[("lib.rs", "fn main() {}")], "update");
+        assert_eq!(
+            summary(20),
+            SubmoduleSummary {
+                uninitialized: 1,
+                out_of_sync: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            summary(1),
+            SubmoduleSummary {
+                out_of_sync: 1,
+                truncated: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_submodule_shows_superproject_name() {
+        let (temp_dir, lib) = init_superproject();
+
+        let info = build_git_info(&lib, &GitConfig::default()).expect("build git info");
+        assert_eq!(
+            info.repo_name,
+            format!("{} › lib", repo_name_from_path(temp_dir.path()))
+        );
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.repo_name, repo_name_from_path(temp_dir.path()));
+        assert_eq!(info.indicators.submodules.uninitialized, 1);
+
+        // The superproject is not looked for in an excluded directory.
+        let root = std::fs::canonicalize(temp_dir.path()).expect("canonical root");
+        let git_config = GitConfig {
+            discovery: crate::config::DiscoveryConfig {
+                exclude: Some(vec![root.to_string_lossy().to_string()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let info = build_git_info(&lib, &git_config).expect("build git info");
+        assert_eq!(info.repo_name, "lib");
+    }
+
+    #[test]
+    #[serial]
+    fn test_identity_guard_checks_remote_rules() {
+        use crate::config::IdentityRule;
+
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        append_git_config(
+            &repo,
+            "[user]\n\temail = me@example.com\n\
+             [remote \"origin\"]\n\turl = git@github.com:ourcorp/pulse.git\n",
+        );
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let identity_with = |email: &str| {
+            let git_config = GitConfig {
+                identity: Some(vec![IdentityRule {
+                    path: None,
+                    remote: Some("github.com/ourcorp/*".to_string()),
+                    email: email.to_string(),
+                }]),
+                ..Default::default()
+            };
+            build_git_info(&repo, &git_config)
+                .expect("build git info")
+                .indicators
+                .identity
+        };
+
+        assert_eq!(identity_with("@ourcorp.com"), IdentityStatus::Mismatch);
+        assert_eq!(identity_with("@example.com"), IdentityStatus::Ok);
+    }
+
+    #[test]
+    #[serial]
+    fn test_head_commit_and_fetch_times() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        assert_eq!(info.head_commit_time, None);
+        assert_eq!(info.last_fetch_time, None);
+
+        commit_files(&repo, &[("a.txt", "a")], "initial");
+        std::fs::write(repo.git_dir().join("FETCH_HEAD"), "").expect("write FETCH_HEAD");
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let info = build_git_info(&repo, &GitConfig::default()).expect("build git info");
+        let commit_time = info.head_commit_time.expect("head commit time");
+        let commit_age = SystemTime::now()
+            .duration_since(commit_time)
+            .expect("commit in the past");
+        assert!(commit_age < Duration::from_secs(3_600));
+        assert!(info.last_fetch_time.is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn test_upstream_status_no_upstream() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        commit_files(&repo, &[("a.txt", "a")], "initial");
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        assert_eq!(
+            get_upstream_status_from_repo(&repo),
+            Some(UpstreamStatus::NoUpstream)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_upstream_status_gone() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        commit_files(&repo, &[("a.txt", "a")], "initial");
+        append_git_config(&repo, ORIGIN_TRACKING_CONFIG);
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        assert_eq!(
+            get_upstream_status_from_repo(&repo),
+            Some(UpstreamStatus::Gone)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_upstream_status_ahead_and_behind() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        let base = commit_files(&repo, &[("a.txt", "a")], "initial");
+        let remote_tip = commit_files(&repo, &[("a.txt", "remote")], "remote work");
+        write_ref(&repo, "refs/remotes/origin/main", remote_tip);
+        write_ref(&repo, "refs/heads/main", base);
+        commit_files(&repo, &[("a.txt", "local 1")], "local work 1");
+        commit_files(&repo, &[("a.txt", "local 2")], "local work 2");
+        append_git_config(&repo, ORIGIN_TRACKING_CONFIG);
+
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        assert_eq!(
+            get_upstream_status_from_repo(&repo),
+            Some(UpstreamStatus::Tracking {
+                ahead: 2,
+                behind: 1
+            })
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_count_ahead_behind_with_equal_commit_times() {
+        let temp_dir = init_temp_git_repo();
+        let repo = discover_git_repo_in(temp_dir.path()).expect("repo");
+        // Commits made within the same second share their timestamp, so the
+        // walk order between them is decided by object id.
+        let chain = (0..6)
+            .map(|i| commit_files(&repo, &[("a.txt", &i.to_string())], "commit"))
+            .collect::<Vec<_>>();
+
+        for (i, local) in chain.iter().enumerate() {
+            for (j, upstream) in chain.iter().enumerate() {
+                assert_eq!(
+                    count_ahead_behind(&repo, *local, *upstream),
+                    Some((i.saturating_sub(j), j.saturating_sub(i))),
+                    "local {} vs upstream {}",
+                    i,
+                    j
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncate_git_path_empty() {
+        assert_eq!(truncate_git_path(&[]), "");
+    }
+
+    #[test]
+    fn test_truncate_git_path_three_parts() {
+        assert_eq!(
+            truncate_git_path(&["src", "main", "rust"]),
+            "src › main › rust"
+        );
+    }
+
+    #[test]
+    fn test_truncate_git_path_four_parts() {
+        assert_eq!(truncate_git_path(&["a", "b", "c", "d"]), "… b › c › d");
+    }
+
+    #[test]
+    fn test_truncate_git_path_more_than_four() {
+        assert_eq!(truncate_git_path(&["x", "y", "z", "a", "b"]), "… z › a › b");
+    }
+
+    #[test]
+    fn test_truncate_non_git_path_inline() {
+        assert_eq!(truncate_non_git_path("~", &["a", "b"]), "~ a › b");
+    }
+
+    #[test]
+    fn test_truncate_non_git_path_dualline_empty() {
+        assert_eq!(truncate_non_git_path("/", &[]), "/");
+    }
+
+    #[test]
+    fn test_truncate_non_git_path_tilde_empty() {
+        assert_eq!(truncate_non_git_path("~", &[]), "~");
+    }
+
+    #[test]
+    fn test_truncate_non_git_path_dualline_three_parts() {
+        assert_eq!(
+            truncate_non_git_path("~", &["home", "user", "docs"]),
+            "~ home › user › docs"
+        );
+    }
+
+    #[test]
+    fn test_truncate_non_git_path_dualline_four_parts() {
+        assert_eq!(
+            truncate_non_git_path("/", &["usr", "local", "bin", "pulse"]),
+            "/ … local › bin › pulse"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_exit_code_default() {
+        // Ensure no env vars are set
+        unsafe {
+            std::env::remove_var("PIPESTATUS");
+            std::env::remove_var("LAST_EXIT_CODE");
+        }
+        assert_eq!(get_exit_code(), "0");
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_exit_code_pipestatus() {
+        unsafe {
+            std::env::remove_var("PIPESTATUS");
+            std::env::remove_var("LAST_EXIT_CODE");
+            std::env::set_var("PIPESTATUS", "42");
+        }
+        assert_eq!(get_exit_code(), "42");
+        unsafe {
+            std::env::remove_var("PIPESTATUS");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_exit_code_last_exit_code() {
+        unsafe {
+            std::env::remove_var("PIPESTATUS");
+            std::env::remove_var("LAST_EXIT_CODE");
+            std::env::set_var("LAST_EXIT_CODE", "1");
+        }
+        assert_eq!(get_exit_code(), "1");
+        unsafe {
+            std::env::remove_var("LAST_EXIT_CODE");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_exit_code_precedence() {
+        unsafe {
+            std::env::remove_var("PIPESTATUS");
+            std::env::remove_var("LAST_EXIT_CODE");
+            std::env::set_var("PIPESTATUS", "10");
+            std::env::set_var("LAST_EXIT_CODE", "20");
+        }
+        assert_eq!(get_exit_code(), "10"); // PIPESTATUS takes precedence
+        unsafe {
+            std::env::remove_var("PIPESTATUS");
+            std::env::remove_var("LAST_EXIT_CODE");
+        }
+    }
+
+    #[test]
+    fn test_is_root_user() {
+        let _ = is_root_user();
+    }
+
+    #[test]
+    fn test_generate_prompt_root_symbol() {
+        let config = crate::config::Config::default();
+        let prompt = generate_prompt(&config);
+        assert!(prompt.is_ok());
+        let p = prompt.expect("prompt should be Ok after is_ok check");
+        // Should contain either $ or # depending on user
+        assert!(p.contains("$") || p.contains("#"));
+    }
+
+    #[test]
+    fn test_generate_prompt() {
+        let config = crate::config::Config::default();
+        let prompt = generate_prompt(&config);
+        assert!(prompt.is_ok());
+        let p = prompt.expect("prompt should be Ok after is_ok check");
+        assert!(p.contains("$ "));
+        assert!(p.lines().count() == 2); // DualLine mode
+    }
+
+    #[test]
+    #[serial]
+    fn test_generate_prompt_inline_git() {
+        let temp_dir = init_temp_git_repo();
+        let _guard = DirGuard::new(temp_dir.path());
+        let config = crate::config::Config {
+            mode: Some("Inline".to_string()),
+            ..Default::default()
+        };
+        let prompt = generate_prompt(&config);
+        assert!(prompt.is_ok());
+        let p = prompt.expect("prompt should be Ok after is_ok check");
+        assert!(p.contains("$ "));
+        assert!(p.lines().count() == 1); // Inline mode
+    }
+
+    #[test]
+    #[serial]
+    fn test_generate_prompt_dualline_git_format() {
+        let temp_dir = init_temp_git_repo();
+        let _guard = DirGuard::new(temp_dir.path());
+        let expected = repo_name_from_path(temp_dir.path());
+        let config = crate::config::Config::default();
+        let prompt = generate_prompt(&config);
+        assert!(prompt.is_ok());
+        let p = prompt.expect("prompt should be Ok after is_ok check");
+        // Should contain repo name and branch in Git format
+        assert!(p.contains(&expected));
+        assert!(p.contains("[")); // start of Git info
+        assert!(p.contains(" : ")); // separator
+        assert!(p.contains("]")); // end of Git info
+        // Should have navigation path
+        assert!(p.lines().count() == 2);
+    }
+
+    #[test]
+    fn test_get_terminal_width() {
+        let width = get_terminal_width();
+        let Some(width) = width else {
+            return;
+        };
+        assert!(width > 0);
+    }
+
+    fn strip_ansi(s: &str) -> String {
+        let mut result = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c == '\x1b' && chars.peek() == Some(&'[') {
+                chars.next(); // skip '['
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphabetic() {
+                        chars.next(); // skip the letter (e.g., 'm')
+                        break;
+                    }
+                    chars.next();
+                }
+                continue;
+            }
+            result.push(c);
+        }
+        result
+    }
+
+    #[test]
+    fn test_git_display_mode_display() {
+        assert_eq!(GitDisplayMode::Full.to_string(), "Full");
+        assert_eq!(GitDisplayMode::Mini.to_string(), "Mini");
+        assert_eq!(GitDisplayMode::Micro.to_string(), "Micro");
+        assert_eq!(GitDisplayMode::Nano.to_string(), "Nano");
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_full_mode() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Full,
+            Some("user@example.com"),
+            "myrepo",
+            "main",
+            "main",
+            &GitIndicators::default(),
+            &["src", "main"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert!(clean.contains("user"));
+        assert!(clean.contains("@"));
+        assert!(clean.contains("example.com"));
+        assert!(clean.contains("[myrepo"));
+        assert!(clean.contains(" : main]"));
+        assert!(clean.contains("src › main"));
     }
 
     #[test]
-    fn test_truncate_non_git_path_dualline_empty() {
-        assert_eq!(truncate_non_git_path("/", &[]), "/");
+    fn test_format_git_prompt_line_mini_mode() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Mini,
+            Some("user@example.com"),
+            "myrepo",
+            "main",
+            "main",
+            &GitIndicators::default(),
+            &["dir1", "dir2", "dir3"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert!(clean.contains("user"));
+        assert!(clean.contains("@example.com"));
+        assert!(clean.contains("[myrepo"));
+        assert!(clean.contains(" : …]"));
+        assert!(clean.contains("dir1 › dir2 › dir3"));
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_micro_mode() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Micro,
+            Some("user@example.com"),
+            "myrepo",
+            "feature-branch",
+            "feature-branch",
+            &GitIndicators::default(),
+            &["src", "utils", "helper"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert!(clean.starts_with("@example.com"));
+        assert!(!clean.contains("user@"));
+        assert!(clean.contains("[myrepo"));
+        assert!(clean.contains(" : …]"));
+        assert!(clean.contains("src › utils › helper"));
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_nano_mode() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Nano,
+            Some("user@example.com"),
+            "myrepo",
+            "develop",
+            "develop",
+            &GitIndicators::default(),
+            &["src", "lib", "core"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert!(clean.starts_with("@example.com"));
+        assert!(!clean.contains("user@"));
+        assert!(clean.contains("[myrepo]"));
+        assert!(!clean.contains(" : "));
+        assert!(clean.contains("… › core"));
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_no_email() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Full,
+            None,
+            "repo",
+            "main",
+            "main",
+            &GitIndicators::default(),
+            &["dir"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert!(clean.contains(": [repo"));
+        assert!(clean.contains(" : main]"));
+        assert!(clean.contains("dir"));
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_nano_single_dir() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Nano,
+            Some("test@domain.org"),
+            "project",
+            "bugfix",
+            "bugfix",
+            &GitIndicators::default(),
+            &["subdir"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert_eq!(clean, "@domain.org: [project] subdir");
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_nano_empty_nav() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Nano,
+            Some("git@domain"),
+            "myrepo",
+            "main",
+            "main",
+            &GitIndicators::default(),
+            &[],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert_eq!(clean, "@domain: [myrepo] ");
+        assert!(!clean.contains("…"));
+        assert!(!clean.contains("›"));
     }
 
-    #[test]
-    fn test_truncate_non_git_path_tilde_empty() {
-        assert_eq!(truncate_non_git_path("~", &[]), "~");
+    #[test]
+    fn test_format_git_prompt_line_micro_empty_nav() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Micro,
+            Some("dev@test.io"),
+            "code",
+            "HEAD",
+            "HEAD",
+            &GitIndicators::default(),
+            &[],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert!(clean.contains("@test.io"));
+        assert!(clean.contains("[code : …]"));
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_full_format() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Full,
+            Some("git@email"),
+            "repo",
+            "branch",
+            "branch",
+            &GitIndicators::default(),
+            &["dir1", "dir2", "dir3"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert_eq!(clean, "git@email: [repo : branch] dir1 › dir2 › dir3");
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_mini_format() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Mini,
+            Some("git@email"),
+            "repo",
+            "branch",
+            "branch",
+            &GitIndicators::default(),
+            &["dir", "dir2", "dir3"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+
+        let clean = strip_ansi(&result);
+        assert_eq!(clean, "git@email: [repo : …] dir › dir2 › dir3");
     }
 
     #[test]
-    fn test_truncate_non_git_path_dualline_three_parts() {
-        assert_eq!(
-            truncate_non_git_path("~", &["home", "user", "docs"]),
-            "~ home › user › docs"
+    fn test_format_git_prompt_line_micro_format() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Micro,
+            Some("git@email"),
+            "repo",
+            "branch",
+            "branch",
+            &GitIndicators::default(),
+            &["dir", "dir2", "dir3"],
+            &colors,
+            &SegmentLayout::default(),
         );
+
+        let clean = strip_ansi(&result);
+        assert_eq!(clean, "@email: [repo : …] dir › dir2 › dir3");
     }
 
     #[test]
-    fn test_truncate_non_git_path_dualline_four_parts() {
-        assert_eq!(
-            truncate_non_git_path("/", &["usr", "local", "bin", "pulse"]),
-            "/ … local › bin › pulse"
+    fn test_format_git_prompt_line_nano_format() {
+        use crate::clrs::Clrs;
+        let colors = PromptColors {
+            user_color: Clrs::Aqua.to_dyn(),
+            host_color: Clrs::Yellow.to_dyn(),
+            git_color: Clrs::Green.to_dyn(),
+            white: Clrs::White.to_dyn(),
+            dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
+        };
+
+        let result = format_git_prompt_line(
+            GitDisplayMode::Nano,
+            Some("git@domain"),
+            "repo",
+            "branch",
+            "branch",
+            &GitIndicators::default(),
+            &["dir1", "dir2", "dir3"],
+            &colors,
+            &SegmentLayout::default(),
         );
-    }
 
-    #[test]
-    #[serial]
-    fn test_get_exit_code_default() {
-        // Ensure no env vars are set
-        unsafe {
-            std::env::remove_var("PIPESTATUS");
-            std::env::remove_var("LAST_EXIT_CODE");
-        }
-        assert_eq!(get_exit_code(), "0");
+        let clean = strip_ansi(&result);
+        assert_eq!(clean, "@domain: [repo] … › dir3");
     }
 
-    #[test]
-    #[serial]
-    fn test_get_exit_code_pipestatus() {
-        unsafe {
-            std::env::remove_var("PIPESTATUS");
-            std::env::remove_var("LAST_EXIT_CODE");
-            std::env::set_var("PIPESTATUS", "42");
-        }
-        assert_eq!(get_exit_code(), "42");
-        unsafe {
-            std::env::remove_var("PIPESTATUS");
+    fn make_dirty_indicators() -> GitIndicators {
+        GitIndicators {
+            status: Some(GitStatus {
+                staged: 2,
+                modified: 1,
+                untracked: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
         }
     }
 
     #[test]
-    #[serial]
-    fn test_get_exit_code_last_exit_code() {
-        unsafe {
-            std::env::remove_var("PIPESTATUS");
-            std::env::remove_var("LAST_EXIT_CODE");
-            std::env::set_var("LAST_EXIT_CODE", "1");
-        }
-        assert_eq!(get_exit_code(), "1");
-        unsafe {
-            std::env::remove_var("LAST_EXIT_CODE");
-        }
-    }
+    fn test_format_git_prompt_line_full_status_counts() {
+        let result = format_git_prompt_line(
+            GitDisplayMode::Full,
+            Some("git@email"),
+            "repo",
+            "branch",
+            "branch",
+            &make_dirty_indicators(),
+            &["dir"],
+            &make_test_colors(),
+            &SegmentLayout::default(),
+        );
 
-    #[test]
-    #[serial]
-    fn test_get_exit_code_precedence() {
-        unsafe {
-            std::env::remove_var("PIPESTATUS");
-            std::env::remove_var("LAST_EXIT_CODE");
-            std::env::set_var("PIPESTATUS", "10");
-            std::env::set_var("LAST_EXIT_CODE", "20");
-        }
-        assert_eq!(get_exit_code(), "10"); // PIPESTATUS takes precedence
-        unsafe {
-            std::env::remove_var("PIPESTATUS");
-            std::env::remove_var("LAST_EXIT_CODE");
-        }
+        let clean = strip_ansi(&result);
+        assert_eq!(clean, "git@email: [repo : branch +2 ~1 ?3] dir");
     }
 
     #[test]
-    fn test_is_root_user() {
-        let _ = is_root_user();
-    }
+    fn test_format_git_prompt_line_submodule_summary() {
+        let indicators = GitIndicators {
+            submodules: SubmoduleSummary {
+                uninitialized: 1,
+                out_of_sync: 2,
+                dirty: 0,
+                truncated: true,
+            },
+            ..make_dirty_indicators()
+        };
+        let render = |mode| {
+            strip_ansi(&format_git_prompt_line(
+                mode,
+                Some("git@email"),
+                "repo",
+                "branch",
+                "branch",
+                &indicators,
+                &["dir"],
+                &make_test_colors(),
+                &SegmentLayout::default(),
+            ))
+        };
 
-    #[test]
-    fn test_generate_prompt_root_symbol() {
-        let config = crate::config::Config::default();
-        let prompt = generate_prompt(&config);
-        assert!(prompt.is_ok());
-        let p = prompt.expect("prompt should be Ok after is_ok check");
-        // Should contain either $ or # depending on user
-        assert!(p.contains("$") || p.contains("#"));
+        assert_eq!(
+            render(GitDisplayMode::Full),
+            "git@email: [repo : branch +2 ~1 ?3 ◫-1+2…] dir"
+        );
+        assert_eq!(render(GitDisplayMode::Mini), "git@email: [repo : … *] dir");
     }
 
     #[test]
-    fn test_generate_prompt() {
-        let config = crate::config::Config::default();
-        let prompt = generate_prompt(&config);
-        assert!(prompt.is_ok());
-        let p = prompt.expect("prompt should be Ok after is_ok check");
-        assert!(p.contains("$ "));
-        assert!(p.lines().count() == 2); // DualLine mode
+    fn test_format_git_prompt_line_clone_markers() {
+        let indicators = GitIndicators {
+            shallow: true,
+            sparse_checkout: true,
+            partial_clone: true,
+            ..make_dirty_indicators()
+        };
+        let render = |mode| {
+            strip_ansi(&format_git_prompt_line(
+                mode,
+                Some("git@email"),
+                "repo",
+                "branch",
+                "branch",
+                &indicators,
+                &["dir"],
+                &make_test_colors(),
+                &SegmentLayout::default(),
+            ))
+        };
+
+        assert_eq!(
+            render(GitDisplayMode::Full),
+            "git@email: [repo : branch ⊥ ◌ ◐ +2 ~1 ?3] dir"
+        );
+        assert_eq!(render(GitDisplayMode::Nano), "@email: [repo ⊥ ◌ ◐ *] dir");
     }
 
     #[test]
-    #[serial]
-    fn test_generate_prompt_inline_git() {
-        let temp_dir = init_temp_git_repo();
-        let _guard = DirGuard::new(temp_dir.path());
-        let config = crate::config::Config {
-            mode: Some("Inline".to_string()),
+    fn test_format_git_prompt_line_partial_marker() {
+        let indicators = GitIndicators {
+            upstream: Some(UpstreamStatus::Tracking {
+                ahead: 1,
+                behind: 0,
+            }),
+            partial: true,
             ..Default::default()
         };
-        let prompt = generate_prompt(&config);
-        assert!(prompt.is_ok());
-        let p = prompt.expect("prompt should be Ok after is_ok check");
-        assert!(p.contains("$ "));
-        assert!(p.lines().count() == 1); // Inline mode
-    }
+        let render = |mode| {
+            strip_ansi(&format_git_prompt_line(
+                mode,
+                Some("git@email"),
+                "repo",
+                "branch",
+                "branch",
+                &indicators,
+                &["dir"],
+                &make_test_colors(),
+                &SegmentLayout::default(),
+            ))
+        };
 
-    #[test]
-    #[serial]
-    fn test_generate_prompt_dualline_git_format() {
-        let temp_dir = init_temp_git_repo();
-        let _guard = DirGuard::new(temp_dir.path());
-        let expected = repo_name_from_path(temp_dir.path());
-        let config = crate::config::Config::default();
-        let prompt = generate_prompt(&config);
-        assert!(prompt.is_ok());
-        let p = prompt.expect("prompt should be Ok after is_ok check");
-        // Should contain repo name and branch in Git format
-        assert!(p.contains(&expected));
-        assert!(p.contains("[")); // start of Git info
-        assert!(p.contains(" : ")); // separator
-        assert!(p.contains("]")); // end of Git info
-        // Should have navigation path
-        assert!(p.lines().count() == 2);
+        assert_eq!(
+            render(GitDisplayMode::Full),
+            "git@email: [repo : branch ↑1 ⧗] dir"
+        );
+        assert_eq!(render(GitDisplayMode::Nano), "@email: [repo ⧗] dir");
     }
 
     #[test]
-    fn test_get_terminal_width() {
-        let width = get_terminal_width();
-        let Some(width) = width else {
-            return;
+    fn test_format_git_prompt_line_ignored_dir() {
+        let indicators = GitIndicators {
+            ignored_dir: true,
+            ..Default::default()
+        };
+        let nav_parts = ["target", "debug"];
+        let render = |mode| {
+            strip_ansi(&format_git_prompt_line(
+                mode,
+                Some("git@email"),
+                "repo",
+                "branch",
+                "branch",
+                &indicators,
+                &nav_parts,
+                &make_test_colors(),
+                &SegmentLayout::default(),
+            ))
         };
-        assert!(width > 0);
-    }
-
-    fn strip_ansi(s: &str) -> String {
-        let mut result = String::new();
-        let mut chars = s.chars().peekable();
 
-        while let Some(c) = chars.next() {
-            if c == '\x1b' && chars.peek() == Some(&'[') {
-                chars.next(); // skip '['
-                while let Some(&next) = chars.peek() {
-                    if next.is_ascii_alphabetic() {
-                        chars.next(); // skip the letter (e.g., 'm')
-                        break;
-                    }
-                    chars.next();
-                }
-                continue;
-            }
-            result.push(c);
-        }
-        result
+        let full = render(GitDisplayMode::Full);
+        assert_eq!(full, "git@email: [repo : branch] ⊘ target › debug");
+        assert_eq!(
+            visual_width(&full),
+            calculate_git_prompt_width(
+                GitDisplayMode::Full,
+                Some("git@email"),
+                "repo",
+                "branch",
+                &indicators,
+                &nav_parts,
+                &make_test_colors(),
+                &SegmentLayout::default(),
+            )
+        );
+        assert_eq!(render(GitDisplayMode::Nano), "@email: [repo] ⊘ … › debug");
     }
 
     #[test]
-    fn test_git_display_mode_display() {
-        assert_eq!(GitDisplayMode::Full.to_string(), "Full");
-        assert_eq!(GitDisplayMode::Mini.to_string(), "Mini");
-        assert_eq!(GitDisplayMode::Micro.to_string(), "Micro");
-        assert_eq!(GitDisplayMode::Nano.to_string(), "Nano");
+    fn test_default_branch_distance_dropped_first() {
+        let indicators = GitIndicators {
+            default_branch: Some(DefaultBranchDistance {
+                name: "main".to_string(),
+                ahead: 3,
+                behind: 12,
+            }),
+            ..make_dirty_indicators()
+        };
+        let colors = make_test_colors();
+        let render = |terminal_width| {
+            let mode = select_display_mode(
+                terminal_width,
+                Some("git@email"),
+                "repo",
+                "branch",
+                &indicators,
+                &["dir"],
+                &colors,
+                &SegmentLayout::default(),
+            );
+            let line = format_git_prompt_line(
+                mode,
+                Some("git@email"),
+                "repo",
+                "branch",
+                "branch",
+                &indicators,
+                &["dir"],
+                &colors,
+                &SegmentLayout::default(),
+            );
+            (mode, strip_ansi(&line))
+        };
+
+        let extended = "git@email: [repo : branch +2 ~1 ?3 +3/-12 vs main] dir";
+        assert_eq!(
+            render(visual_width(extended) as u16),
+            (GitDisplayMode::Extended, extended.to_string())
+        );
+        assert_eq!(
+            render(visual_width(extended) as u16 - 1),
+            (
+                GitDisplayMode::Full,
+                "git@email: [repo : branch +2 ~1 ?3] dir".to_string()
+            )
+        );
+
+        let without_distance = make_dirty_indicators();
+        let mode = select_display_mode(
+            200,
+            Some("git@email"),
+            "repo",
+            "branch",
+            &without_distance,
+            &["dir"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+        assert_eq!(mode, GitDisplayMode::Full);
     }
 
     #[test]
-    fn test_format_git_prompt_line_full_mode() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
-        };
-
+    fn test_format_git_prompt_line_nano_dirty_marker() {
         let result = format_git_prompt_line(
-            GitDisplayMode::Full,
-            Some("user@example.com"),
-            "myrepo",
-            "main",
-            &["src", "main"],
-            &colors,
+            GitDisplayMode::Nano,
+            Some("git@domain"),
+            "repo",
+            "branch",
+            "branch",
+            &make_dirty_indicators(),
+            &["dir"],
+            &make_test_colors(),
+            &SegmentLayout::default(),
         );
 
         let clean = strip_ansi(&result);
-        assert!(clean.contains("user"));
-        assert!(clean.contains("@"));
-        assert!(clean.contains("example.com"));
-        assert!(clean.contains("[myrepo"));
-        assert!(clean.contains(" : main]"));
-        assert!(clean.contains("src › main"));
+        assert_eq!(clean, "@domain: [repo *] dir");
     }
 
     #[test]
-    fn test_format_git_prompt_line_mini_mode() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
+    fn test_format_git_prompt_line_clean_status_has_no_marker() {
+        let indicators = GitIndicators {
+            status: Some(GitStatus::default()),
+            ..Default::default()
         };
-
         let result = format_git_prompt_line(
-            GitDisplayMode::Mini,
-            Some("user@example.com"),
-            "myrepo",
-            "main",
-            &["dir1", "dir2", "dir3"],
-            &colors,
+            GitDisplayMode::Full,
+            Some("git@email"),
+            "repo",
+            "branch",
+            "branch",
+            &indicators,
+            &["dir"],
+            &make_test_colors(),
+            &SegmentLayout::default(),
         );
 
         let clean = strip_ansi(&result);
-        assert!(clean.contains("user"));
-        assert!(clean.contains("@example.com"));
-        assert!(clean.contains("[myrepo"));
-        assert!(clean.contains(" : …]"));
-        assert!(clean.contains("dir1 › dir2 › dir3"));
+        assert_eq!(clean, "git@email: [repo : branch] dir");
     }
 
     #[test]
-    fn test_format_git_prompt_line_micro_mode() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
+    fn test_format_git_prompt_line_full_upstream() {
+        let indicators = GitIndicators {
+            upstream: Some(UpstreamStatus::Tracking {
+                ahead: 2,
+                behind: 1,
+            }),
+            ..Default::default()
         };
-
         let result = format_git_prompt_line(
-            GitDisplayMode::Micro,
-            Some("user@example.com"),
-            "myrepo",
-            "feature-branch",
-            &["src", "utils", "helper"],
-            &colors,
+            GitDisplayMode::Full,
+            Some("git@email"),
+            "repo",
+            "branch",
+            "branch",
+            &indicators,
+            &["dir"],
+            &make_test_colors(),
+            &SegmentLayout::default(),
         );
 
         let clean = strip_ansi(&result);
-        assert!(clean.starts_with("@example.com"));
-        assert!(!clean.contains("user@"));
-        assert!(clean.contains("[myrepo"));
-        assert!(clean.contains(" : …]"));
-        assert!(clean.contains("src › utils › helper"));
+        assert_eq!(clean, "git@email: [repo : branch ↑2↓1] dir");
     }
 
     #[test]
-    fn test_format_git_prompt_line_nano_mode() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
-        };
+    fn test_format_git_prompt_line_upstream_markers() {
+        for (upstream, expected) in [
+            (UpstreamStatus::NoUpstream, "[repo : branch ∅]"),
+            (UpstreamStatus::Gone, "[repo : branch ✗]"),
+            (
+                UpstreamStatus::Tracking {
+                    ahead: 0,
+                    behind: 0,
+                },
+                "[repo : branch]",
+            ),
+        ] {
+            let indicators = GitIndicators {
+                upstream: Some(upstream),
+                ..Default::default()
+            };
+            let result = format_git_prompt_line(
+                GitDisplayMode::Full,
+                None,
+                "repo",
+                "branch",
+                "branch",
+                &indicators,
+                &[],
+                &make_test_colors(),
+                &SegmentLayout::default(),
+            );
+            assert!(strip_ansi(&result).contains(expected), "{upstream:?}");
+        }
+    }
 
+    #[test]
+    fn test_format_git_prompt_line_compact_modes_drop_upstream() {
+        let indicators = GitIndicators {
+            upstream: Some(UpstreamStatus::Tracking {
+                ahead: 3,
+                behind: 0,
+            }),
+            ..Default::default()
+        };
         let result = format_git_prompt_line(
-            GitDisplayMode::Nano,
-            Some("user@example.com"),
-            "myrepo",
-            "develop",
-            &["src", "lib", "core"],
-            &colors,
+            GitDisplayMode::Mini,
+            Some("git@email"),
+            "repo",
+            "branch",
+            "branch",
+            &indicators,
+            &["dir"],
+            &make_test_colors(),
+            &SegmentLayout::default(),
         );
 
         let clean = strip_ansi(&result);
-        assert!(clean.starts_with("@example.com"));
-        assert!(!clean.contains("user@"));
-        assert!(clean.contains("[myrepo]"));
-        assert!(!clean.contains(" : "));
-        assert!(clean.contains("… › core"));
+        assert_eq!(clean, "git@email: [repo : …] dir");
     }
 
-    #[test]
-    fn test_format_git_prompt_line_no_email() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
-        };
+    fn make_rebase_indicators() -> GitIndicators {
+        GitIndicators {
+            operation: Some(GitOperation {
+                label: "REBASE-i",
+                progress: Some((3, 7)),
+                head_name: Some("feature".to_string()),
+            }),
+            status: Some(GitStatus {
+                conflicted: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
 
+    #[test]
+    fn test_format_git_prompt_line_full_operation() {
         let result = format_git_prompt_line(
             GitDisplayMode::Full,
-            None,
+            Some("git@email"),
             "repo",
-            "main",
+            "feature",
+            "feature",
+            &make_rebase_indicators(),
             &["dir"],
-            &colors,
+            &make_test_colors(),
+            &SegmentLayout::default(),
         );
 
         let clean = strip_ansi(&result);
-        assert!(clean.contains(": [repo"));
-        assert!(clean.contains(" : main]"));
-        assert!(clean.contains("dir"));
+        assert_eq!(clean, "git@email: [repo : feature REBASE-i 3/7 !1] dir");
     }
 
     #[test]
-    fn test_format_git_prompt_line_nano_single_dir() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
-        };
-
+    fn test_format_git_prompt_line_nano_keeps_operation() {
         let result = format_git_prompt_line(
             GitDisplayMode::Nano,
-            Some("test@domain.org"),
-            "project",
-            "bugfix",
-            &["subdir"],
-            &colors,
+            Some("git@domain"),
+            "repo",
+            "feature",
+            "feature",
+            &make_rebase_indicators(),
+            &["dir"],
+            &make_test_colors(),
+            &SegmentLayout::default(),
         );
 
         let clean = strip_ansi(&result);
-        assert_eq!(clean, "@domain.org: [project] subdir");
+        assert_eq!(clean, "@domain: [repo REBASE-i 3/7 *] dir");
     }
 
     #[test]
-    fn test_format_git_prompt_line_nano_empty_nav() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
+    fn test_format_git_prompt_line_stash_and_worktree() {
+        let indicators = GitIndicators {
+            stash: 2,
+            linked_worktree: true,
+            ..make_dirty_indicators()
         };
+        let colors = make_test_colors();
+        let full = format_git_prompt_line(
+            GitDisplayMode::Full,
+            Some("git@email"),
+            "repo",
+            "feature",
+            "feature",
+            &indicators,
+            &["dir"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+        assert_eq!(
+            strip_ansi(&full),
+            "git@email: [repo : feature ⎇ +2 ~1 ?3 $2] dir"
+        );
 
-        let result = format_git_prompt_line(
+        let nano = format_git_prompt_line(
             GitDisplayMode::Nano,
             Some("git@domain"),
-            "myrepo",
-            "main",
-            &[],
+            "repo",
+            "feature",
+            "feature",
+            &indicators,
+            &["dir"],
             &colors,
+            &SegmentLayout::default(),
         );
-
-        let clean = strip_ansi(&result);
-        assert_eq!(clean, "@domain: [myrepo] ");
-        assert!(!clean.contains("…"));
-        assert!(!clean.contains("›"));
+        assert_eq!(strip_ansi(&nano), "@domain: [repo ⎇ *] dir");
     }
 
     #[test]
-    fn test_format_git_prompt_line_micro_empty_nav() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
+    fn test_calculate_git_prompt_width_matches_indicator_rendering() {
+        let indicators = GitIndicators {
+            upstream: Some(UpstreamStatus::Tracking {
+                ahead: 1,
+                behind: 12,
+            }),
+            operation: make_rebase_indicators().operation,
+            stash: 4,
+            linked_worktree: true,
+            ..make_dirty_indicators()
         };
-
-        let result = format_git_prompt_line(
+        let colors = make_test_colors();
+        for mode in [
+            GitDisplayMode::Full,
+            GitDisplayMode::Mini,
             GitDisplayMode::Micro,
-            Some("dev@test.io"),
-            "code",
-            "HEAD",
-            &[],
+            GitDisplayMode::Nano,
+        ] {
+            let rendered = format_git_prompt_line(
+                mode,
+                Some("user@example.com"),
+                "myrepo",
+                "main",
+                "main",
+                &indicators,
+                &["src"],
+                &colors,
+                &SegmentLayout::default(),
+            );
+            let width = calculate_git_prompt_width(
+                mode,
+                Some("user@example.com"),
+                "myrepo",
+                "main",
+                &indicators,
+                &["src"],
+                &colors,
+                &SegmentLayout::default(),
+            );
+            assert_eq!(width, visual_width(&strip_ansi(&rendered)), "mode {mode}");
+        }
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_identity_mismatch() {
+        let indicators = GitIndicators {
+            identity: IdentityStatus::Mismatch,
+            ..Default::default()
+        };
+        let colors = make_test_colors();
+        let full = format_git_prompt_line(
+            GitDisplayMode::Full,
+            Some("me@example.com"),
+            "repo",
+            "main",
+            "main",
+            &indicators,
+            &["dir"],
             &colors,
+            &SegmentLayout::default(),
         );
+        assert_eq!(strip_ansi(&full), "⚠ me@example.com: [repo : main] dir");
+        assert!(full.contains(&format!("{}", "me".color(colors.warning).bold())));
 
-        let clean = strip_ansi(&result);
-        assert!(clean.contains("@test.io"));
-        assert!(clean.contains("[code : …]"));
+        let nano = format_git_prompt_line(
+            GitDisplayMode::Nano,
+            Some("me@example.com"),
+            "repo",
+            "main",
+            "main",
+            &indicators,
+            &["dir"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+        assert_eq!(strip_ansi(&nano), "⚠ @example.com: [repo] dir");
     }
 
     #[test]
-    fn test_format_git_prompt_line_full_format() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
+    fn test_format_git_prompt_line_identity_missing() {
+        let indicators = GitIndicators {
+            identity: IdentityStatus::Missing,
+            ..Default::default()
         };
-
-        let result = format_git_prompt_line(
+        let colors = make_test_colors();
+        let full = format_git_prompt_line(
             GitDisplayMode::Full,
-            Some("git@email"),
+            None,
             "repo",
-            "branch",
-            &["dir1", "dir2", "dir3"],
+            "main",
+            "main",
+            &indicators,
+            &["dir"],
+            &colors,
+            &SegmentLayout::default(),
+        );
+        assert_eq!(strip_ansi(&full), "⚠ no email: [repo : main] dir");
+
+        let nano = format_git_prompt_line(
+            GitDisplayMode::Nano,
+            None,
+            "repo",
+            "main",
+            "main",
+            &indicators,
+            &["dir"],
             &colors,
+            &SegmentLayout::default(),
+        );
+        assert_eq!(strip_ansi(&nano), "⚠: [repo] dir");
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_untrusted_repo() {
+        let indicators = GitIndicators {
+            identity: IdentityStatus::Untrusted,
+            ..Default::default()
+        };
+        let colors = make_test_colors();
+        let render = |mode| {
+            let line = format_git_prompt_line(
+                mode,
+                None,
+                "repo",
+                "main",
+                "main",
+                &indicators,
+                &["dir"],
+                &colors,
+                &SegmentLayout::default(),
+            );
+            strip_ansi(&line)
+        };
+
+        assert_eq!(
+            render(GitDisplayMode::Full),
+            "⚠ untrusted repo: [repo : main] dir"
+        );
+        assert_eq!(render(GitDisplayMode::Nano), "⚠: [repo] dir");
+    }
+
+    #[test]
+    fn test_calculate_git_prompt_width_matches_identity_rendering() {
+        let colors = make_test_colors();
+        for (mode, email, identity) in [
+            (
+                GitDisplayMode::Full,
+                Some("me@example.com"),
+                IdentityStatus::Mismatch,
+            ),
+            (
+                GitDisplayMode::Micro,
+                Some("me@example.com"),
+                IdentityStatus::Mismatch,
+            ),
+            (GitDisplayMode::Full, None, IdentityStatus::Missing),
+            (GitDisplayMode::Mini, None, IdentityStatus::Missing),
+            (GitDisplayMode::Full, None, IdentityStatus::Untrusted),
+        ] {
+            let indicators = GitIndicators {
+                identity,
+                ..Default::default()
+            };
+            let rendered = format_git_prompt_line(
+                mode,
+                email,
+                "myrepo",
+                "main",
+                "main",
+                &indicators,
+                &["src"],
+                &colors,
+                &SegmentLayout::default(),
+            );
+            let width = calculate_git_prompt_width(
+                mode,
+                email,
+                "myrepo",
+                "main",
+                &indicators,
+                &["src"],
+                &colors,
+                &SegmentLayout::default(),
+            );
+            assert_eq!(width, visual_width(&strip_ansi(&rendered)), "mode {mode}");
+        }
+    }
+
+    fn make_branch_color_config() -> Config {
+        use crate::config::BranchColorRule;
+
+        let mut config = Config::default();
+        config.git.branch_colors = Some(
+            ["main", "master", "release/*"]
+                .into_iter()
+                .map(|pattern| BranchColorRule {
+                    pattern: pattern.to_string(),
+                    color: Some("Red".to_string()),
+                    style: Some("bold".to_string()),
+                })
+                .chain([BranchColorRule {
+                    pattern: "wip/*".to_string(),
+                    color: None,
+                    style: Some("dimmed".to_string()),
+                }])
+                .collect(),
         );
+        config
+    }
 
-        let clean = strip_ansi(&result);
-        assert_eq!(clean, "git@email: [repo : branch] dir1 › dir2 › dir3");
+    #[test]
+    fn test_prompt_colors_branch_style() {
+        let config = make_branch_color_config();
+        let colors = PromptColors::from_config(&config);
+        let protected = Style::new().color(Clrs::Red.to_dyn()).bold();
+        let default = Style::new().color(config.get_color("git_branch").to_dyn());
+
+        for (branch, expected) in [
+            ("main", protected),
+            ("release/1.4", protected),
+            ("release/1.4/hotfix", default),
+            ("feature/x", default),
+            ("wip/x", default.dimmed()),
+        ] {
+            assert_eq!(
+                format!("{}", branch.style(colors.branch_style(branch))),
+                format!("{}", branch.style(expected)),
+                "branch {branch}"
+            );
+        }
     }
 
     #[test]
-    fn test_format_git_prompt_line_mini_format() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
-        };
+    fn test_format_git_prompt_line_applies_branch_style() {
+        let colors = PromptColors::from_config(&make_branch_color_config());
+        let protected = Style::new().color(Clrs::Red.to_dyn()).bold();
 
-        let result = format_git_prompt_line(
-            GitDisplayMode::Mini,
+        let full = format_git_prompt_line(
+            GitDisplayMode::Full,
             Some("git@email"),
             "repo",
-            "branch",
-            &["dir", "dir2", "dir3"],
+            "main",
+            "main",
+            &GitIndicators::default(),
+            &["dir"],
             &colors,
+            &SegmentLayout::default(),
         );
+        assert!(full.contains(&format!("{}", "main".style(protected))));
+        assert!(!full.contains(&format!("{}", "repo".style(protected))));
 
-        let clean = strip_ansi(&result);
-        assert_eq!(clean, "git@email: [repo : …] dir › dir2 › dir3");
-    }
-
-    #[test]
-    fn test_format_git_prompt_line_micro_format() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
-        };
-
-        let result = format_git_prompt_line(
-            GitDisplayMode::Micro,
+        let mini = format_git_prompt_line(
+            GitDisplayMode::Mini,
             Some("git@email"),
             "repo",
-            "branch",
-            &["dir", "dir2", "dir3"],
+            "main",
+            "main",
+            &GitIndicators::default(),
+            &["dir"],
             &colors,
+            &SegmentLayout::default(),
         );
-
-        let clean = strip_ansi(&result);
-        assert_eq!(clean, "@email: [repo : …] dir › dir2 › dir3");
+        assert!(mini.contains(&format!("{}", "…".style(protected))));
     }
 
     #[test]
-    fn test_format_git_prompt_line_nano_format() {
-        use crate::clrs::Clrs;
-        let colors = PromptColors {
-            user_color: Clrs::Aqua.to_dyn(),
-            host_color: Clrs::Yellow.to_dyn(),
-            git_color: Clrs::Green.to_dyn(),
-            white: Clrs::White.to_dyn(),
-            dir_color: Clrs::Blue.to_dyn(),
+    fn test_branch_style_matches_full_branch() {
+        let mut config = make_branch_color_config();
+        config.git.branch = BranchConfig {
+            strip_prefixes: Some(vec!["release/".to_string()]),
+            ..Default::default()
         };
+        config.template = Some("{git} {branch}".to_string());
+        let git_info = GitInfo {
+            repo_name: "pulse".to_string(),
+            branch: "release/1.4".to_string(),
+            user_email: None,
+            work_dir: PathBuf::from("/repo"),
+            indicators: GitIndicators::default(),
+            head_commit_time: None,
+            last_fetch_time: None,
+            color: None,
+        };
+        let prompt = PromptBuilder::from_config(&config)
+            .git_info(Some(git_info))
+            .current_dir_path(PathBuf::from("/repo"))
+            .user("alice")
+            .host("devbox")
+            .dir("~/work/pulse")
+            .render()
+            .expect("prompt render");
 
-        let result = format_git_prompt_line(
-            GitDisplayMode::Nano,
-            Some("git@domain"),
-            "repo",
-            "branch",
-            &["dir1", "dir2", "dir3"],
-            &colors,
-        );
-
-        let clean = strip_ansi(&result);
-        assert_eq!(clean, "@domain: [repo] … › dir3");
+        let protected = Style::new().color(Clrs::Red.to_dyn()).bold();
+        let protected = format!("{}", "1.4".style(protected));
+        assert_eq!(prompt.matches(&protected).count(), 2);
     }
 
     fn make_test_colors() -> PromptColors {
@@ -1395,6 +5289,8 @@ mod tests {
             git_color: Clrs::Green.to_dyn(),
             white: Clrs::White.to_dyn(),
             dir_color: Clrs::Blue.to_dyn(),
+            warning: Clrs::Yellow.to_dyn(),
+            branch_rules: Vec::new(),
         }
     }
 
@@ -1442,8 +5338,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src", "lib"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Nano);
     }
@@ -1455,8 +5353,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Micro);
     }
@@ -1468,8 +5368,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Mini);
     }
@@ -1481,8 +5383,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src", "main", "rust"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Full);
     }
@@ -1494,8 +5398,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src", "main"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Full);
     }
@@ -1507,8 +5413,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Mini);
     }
@@ -1520,8 +5428,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Nano);
     }
@@ -1533,8 +5443,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Nano);
     }
@@ -1546,8 +5458,10 @@ mod tests {
             Some("user@example.com"),
             "myrepo",
             "main",
+            &GitIndicators::default(),
             &["src"],
             &make_test_colors(),
+            &SegmentLayout::default(),
         );
         assert_eq!(result, GitDisplayMode::Nano);
     }
@@ -1577,6 +5491,310 @@ mod tests {
         assert_eq!(clean, "alice@devbox:~ work › pulse $ ");
     }
 
+    fn make_layout(segments: &[(&str, Option<&str>)]) -> Config {
+        use crate::config::SegmentConfig;
+
+        Config {
+            mode: Some("Inline".to_string()),
+            segments: segments
+                .iter()
+                .map(|(name, separator)| SegmentConfig {
+                    name: name.to_string(),
+                    color: None,
+                    separator: separator.map(str::to_string),
+                    enabled: None,
+                    command: None,
+                    env: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_prompt_builder_follows_segment_layout() {
+        let render = |config: &Config, git_info: Option<GitInfo>| {
+            let prompt = PromptBuilder::from_config(config)
+                .git_info(git_info)
+                .current_dir_path(PathBuf::from("/repo/src/lib"))
+                .user("alice")
+                .host("devbox")
+                .dir("~/work/pulse")
+                .render()
+                .expect("prompt render");
+            strip_ansi(&prompt)
+        };
+        let git_info = GitInfo {
+            repo_name: "pulse".to_string(),
+            branch: "main".to_string(),
+            user_email: Some("dev@example.com".to_string()),
+            work_dir: PathBuf::from("/repo"),
+            indicators: GitIndicators::default(),
+            head_commit_time: None,
+            last_fetch_time: None,
+            color: None,
+        };
+
+        let without_host = make_layout(&[
+            ("username", None),
+            ("current_directory", None),
+            ("git_branch", None),
+        ]);
+        assert_eq!(render(&without_host, None), "alice:~ work › pulse $ ");
+        assert_eq!(
+            render(&without_host, Some(git_info.clone())),
+            "dev:src › lib [pulse : main] $ "
+        );
+
+        let git_first = make_layout(&[
+            ("git_branch", None),
+            ("current_directory", Some(" in ")),
+            ("hostname", Some(" on ")),
+        ]);
+        assert_eq!(render(&git_first, None), "~ work › pulse on devbox $ ");
+        assert_eq!(
+            render(&git_first, Some(git_info)),
+            "[pulse : main] in src › lib on example.com $ "
+        );
+    }
+
+    #[test]
+    fn test_prompt_builder_renders_template() {
+        let render = |template: &str, git_info: Option<GitInfo>| {
+            let config = Config {
+                template: Some(template.to_string()),
+                ..Default::default()
+            };
+            PromptBuilder::from_config(&config)
+                .git_info(git_info)
+                .current_dir_path(PathBuf::from("/repo/src"))
+                .user("alice")
+                .host("devbox")
+                .dir("~/work/pulse")
+                .exit_code("1")
+                .render()
+                .expect("prompt render")
+        };
+        let git_info = GitInfo {
+            repo_name: "pulse".to_string(),
+            branch: "main".to_string(),
+            user_email: Some("dev@example.com".to_string()),
+            work_dir: PathBuf::from("/repo"),
+            indicators: GitIndicators::default(),
+            head_commit_time: None,
+            last_fetch_time: None,
+            color: None,
+        };
+        let template = "{user}@{host}:{dir}{git? [{repo}:{branch}]}\n└─ {exit} {symbol} ";
+
+        assert_eq!(
+            strip_ansi(&render(template, None)),
+            "alice@devbox:~/work/pulse\n└─ 1 $ "
+        );
+        assert_eq!(
+            strip_ansi(&render(template, Some(git_info.clone()))),
+            "alice@devbox:~/work/pulse [pulse:main]\n└─ 1 $ "
+        );
+        assert_eq!(
+            strip_ansi(&render("{line} {git} {symbol} ", Some(git_info))),
+            "dev@example.com: [pulse : main] src [pulse : main] $ "
+        );
+
+        let prompt = render("{#Red bold}{symbol}{#} {user}", None);
+        let red = Style::new().color(Clrs::Red.to_dyn()).bold();
+        let blue = Style::new().color(Clrs::Blue.to_dyn());
+        assert_eq!(
+            prompt,
+            format!("{} {}", "$".style(red), "alice".style(blue))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_prompt_builder_renders_command_segments() {
+        let cwd = TempDir::new().expect("temp dir");
+        let config_path = cwd.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            r#"mode: Inline
+segments:
+  - name: hostname
+    enabled: false
+  - name: git_branch
+    enabled: false
+  - name: current_directory
+  - name: shift
+    color: Olive
+    command:
+      run: "echo on-call: alice"
+  - name: toolbox
+    color: Olive
+    command:
+      run: "echo degraded; exit 1"
+      status_colors:
+        - status: 1
+          color: Maroon
+  - name: quiet
+    command:
+      run: "true"
+"#,
+        )
+        .expect("write config");
+        let config = Config::load_from_path(&config_path).expect("config");
+        let previous_cache = std::env::var_os("XDG_CACHE_HOME");
+        unsafe { std::env::set_var("XDG_CACHE_HOME", cwd.path()) };
+        let prompt = PromptBuilder::from_config(&config)
+            .current_dir_path(cwd.path().to_path_buf())
+            .user("alice")
+            .host("devbox")
+            .dir("~/work")
+            .render()
+            .expect("prompt render");
+        match previous_cache {
+            Some(previous_cache) => unsafe { std::env::set_var("XDG_CACHE_HOME", previous_cache) },
+            None => unsafe { std::env::remove_var("XDG_CACHE_HOME") },
+        }
+
+        assert_eq!(
+            strip_ansi(&prompt),
+            "alice:~ work on-call: alice degraded $ "
+        );
+        let painted =
+            |text: &str, color: Clrs| format!("{}", text.style(Style::new().color(color.to_dyn())));
+        assert!(prompt.contains(&painted("on-call: alice", Clrs::Olive)));
+        assert!(prompt.contains(&painted("degraded", Clrs::Maroon)));
+        assert!(cwd.path().join("pulse/commands").is_dir());
+    }
+
+    #[test]
+    fn test_env_segments_map_and_hide_values() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let config_path = temp_dir.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            r#"mode: Inline
+segments:
+  - name: hostname
+    enabled: false
+  - name: git_branch
+    enabled: false
+  - name: current_directory
+  - name: deploy
+    color: Green
+    env:
+      var: DEPLOY_ENV
+      format: "env:{value}"
+      values:
+        - value: prod
+          text: PROD
+          color: Red
+          style: bold
+  - name: tenant
+    env:
+      var: TENANT
+      hide: unset
+"#,
+        )
+        .expect("write config");
+        let config = Config::load_from_path(&config_path).expect("config");
+        let colors = make_test_colors();
+        let render = |vars: &[(&str, &str)]| {
+            let mut layout = SegmentLayout::from_config(&config);
+            layout.read_env(|var| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            });
+            build_non_git_path_string("~/work", "alice", "devbox", &colors, &layout)
+        };
+
+        let prompt = render(&[("DEPLOY_ENV", "prod"), ("TENANT", "acme")]);
+        assert_eq!(strip_ansi(&prompt), "alice:~ work env:PROD acme");
+        let prod = Style::new().color(Clrs::Red.to_dyn()).bold();
+        assert!(prompt.contains(&format!("{}", "env:PROD".style(prod))));
+
+        let prompt = render(&[("DEPLOY_ENV", "staging")]);
+        assert_eq!(strip_ansi(&prompt), "alice:~ work env:staging");
+        let staging = Style::new().color(Clrs::Green.to_dyn());
+        assert!(prompt.contains(&format!("{}", "env:staging".style(staging))));
+
+        assert_eq!(
+            strip_ansi(&render(&[("DEPLOY_ENV", ""), ("TENANT", "")])),
+            "alice:~ work"
+        );
+    }
+
+    #[test]
+    fn test_env_segment_hide_rules() {
+        let segment = |hide: &str| {
+            let env = EnvConfig {
+                var: "TENANT".to_string(),
+                format: Some("[{value}]".to_string()),
+                hide: Some(hide.to_string()),
+                ..Default::default()
+            };
+            let segment = SegmentConfig {
+                name: "tenant".to_string(),
+                color: None,
+                separator: None,
+                enabled: None,
+                command: None,
+                env: Some(env.clone()),
+            };
+            EnvSegment::from_config(&segment, &env, &Config::default())
+        };
+        let text = |segment: &EnvSegment, value: Option<&str>| {
+            segment
+                .render(value)
+                .map(|rendered| strip_ansi(&rendered.text))
+        };
+
+        let empty = segment("empty");
+        assert_eq!(text(&empty, Some("acme")).as_deref(), Some("[acme]"));
+        assert_eq!(text(&empty, Some("")), None);
+        assert_eq!(text(&empty, None), None);
+
+        let unset = segment("unset");
+        assert_eq!(text(&unset, Some("")).as_deref(), Some("[]"));
+        assert_eq!(text(&unset, None), None);
+
+        let never = segment("never");
+        assert_eq!(text(&never, None).as_deref(), Some("[]"));
+    }
+
+    #[test]
+    fn test_select_display_mode_uses_segment_layout() {
+        let layout = SegmentLayout::from_config(&make_layout(&[
+            ("git_branch", None),
+            ("current_directory", None),
+        ]));
+        let colors = make_test_colors();
+        let line = format_git_prompt_line(
+            GitDisplayMode::Full,
+            Some("user@example.com"),
+            "myrepo",
+            "main",
+            "main",
+            &GitIndicators::default(),
+            &["src"],
+            &colors,
+            &layout,
+        );
+        assert_eq!(strip_ansi(&line), "[myrepo : main] src");
+
+        let mode = select_display_mode(
+            visual_width("[myrepo : main] src") as u16,
+            Some("user@example.com"),
+            "myrepo",
+            "main",
+            &GitIndicators::default(),
+            &["src"],
+            &colors,
+            &layout,
+        );
+        assert_eq!(mode, GitDisplayMode::Full);
+    }
+
     #[test]
     fn test_prompt_builder_dualline_non_git() {
         let config = Config::default();
@@ -1614,6 +5832,10 @@ mod tests {
             branch: "main".to_string(),
             user_email: Some("dev@example.com".to_string()),
             work_dir: PathBuf::from("/repo"),
+            indicators: GitIndicators::default(),
+            head_commit_time: None,
+            last_fetch_time: None,
+            color: None,
         };
         let prompt = PromptBuilder::from_config(&config)
             .terminal_width(200)
@@ -1644,6 +5866,10 @@ mod tests {
             branch: "main".to_string(),
             user_email: Some("dev@example.com".to_string()),
             work_dir: PathBuf::from("/repo"),
+            indicators: GitIndicators::default(),
+            head_commit_time: None,
+            last_fetch_time: None,
+            color: None,
         };
         let prompt = PromptBuilder::from_config(&config)
             .terminal_width(200)
@@ -1670,6 +5896,211 @@ mod tests {
         assert_eq!(second, "└─ 9 $ ");
     }
 
+    #[test]
+    fn test_prompt_builder_shortens_branch_in_compact_modes() {
+        let mut config = Config {
+            mode: Some("Inline".to_string()),
+            ..Default::default()
+        };
+        config.git.branch = BranchConfig {
+            strip_prefixes: Some(vec!["feature/".to_string()]),
+            ticket_pattern: Some("[A-Z]+-[0-9]+".to_string()),
+            max_length: None,
+        };
+        let git_info = GitInfo {
+            repo_name: "pulse".to_string(),
+            branch: "feature/PROJ-1234-some-very-long-description".to_string(),
+            user_email: Some("dev@example.com".to_string()),
+            work_dir: PathBuf::from("/repo"),
+            indicators: GitIndicators::default(),
+            head_commit_time: None,
+            last_fetch_time: None,
+            color: None,
+        };
+        let render = |terminal_width| {
+            let prompt = PromptBuilder::from_config(&config)
+                .terminal_width(terminal_width)
+                .exit_code("0")
+                .root(false)
+                .git_info(Some(git_info.clone()))
+                .current_dir_path(PathBuf::from("/repo/src/lib"))
+                .user("unused")
+                .host("unused")
+                .dir("/repo/src/lib")
+                .render()
+                .expect("prompt render");
+            strip_ansi(&prompt)
+        };
+
+        assert_eq!(
+            render(200),
+            "dev@example.com: [pulse : PROJ-1234] src › lib $ "
+        );
+        assert_eq!(render(44), "@example.com: [pulse : PROJ-1234] src › lib $ ");
+    }
+
+    #[test]
+    fn test_prompt_builder_commit_age_and_stale_fetch() {
+        let config = Config {
+            mode: Some("Inline".to_string()),
+            ..Default::default()
+        };
+        let now = SystemTime::now();
+        let git_info = GitInfo {
+            repo_name: "pulse".to_string(),
+            branch: "main".to_string(),
+            user_email: Some("dev@example.com".to_string()),
+            work_dir: PathBuf::from("/repo"),
+            indicators: GitIndicators::default(),
+            head_commit_time: Some(now - Duration::from_secs(3 * 3_600 + 60)),
+            last_fetch_time: Some(now - Duration::from_secs(2 * 86_400)),
+            color: None,
+        };
+        let render = |config: &Config, terminal_width| {
+            let prompt = PromptBuilder::from_config(config)
+                .terminal_width(terminal_width)
+                .exit_code("0")
+                .root(false)
+                .git_info(Some(git_info.clone()))
+                .current_dir_path(PathBuf::from("/repo/src"))
+                .user("unused")
+                .host("unused")
+                .dir("/repo/src")
+                .render()
+                .expect("prompt render");
+            strip_ansi(&prompt)
+        };
+
+        assert_eq!(
+            render(&config, 200),
+            "dev@example.com: [pulse : main ⟳ 3h] src $ "
+        );
+        assert_eq!(render(&config, 30), "@example.com: [pulse : …] src $ ");
+
+        let mut relaxed = config.clone();
+        relaxed.git.stale_fetch_after = Some("1w".to_string());
+        assert_eq!(
+            render(&relaxed, 200),
+            "dev@example.com: [pulse : main 3h] src $ "
+        );
+    }
+
+    #[test]
+    fn test_prompt_builder_uses_repo_color() {
+        let config = Config::default();
+        let render = |color| {
+            PromptBuilder::from_config(&config)
+                .git_info(Some(GitInfo {
+                    repo_name: "pulse".to_string(),
+                    branch: "main".to_string(),
+                    user_email: None,
+                    work_dir: PathBuf::from("/repo"),
+                    indicators: GitIndicators::default(),
+                    head_commit_time: None,
+                    last_fetch_time: None,
+                    color,
+                }))
+                .current_dir_path(PathBuf::from("/repo"))
+                .user("unused")
+                .host("unused")
+                .dir("/repo")
+                .render()
+                .expect("prompt render")
+        };
+
+        let name = format!("{}", "pulse".color(Clrs::Fuchsia.to_dyn()));
+        assert!(render(Some(Clrs::Fuchsia)).contains(&name));
+        assert!(!render(None).contains(&name));
+    }
+
+    #[test]
+    fn test_format_relative_duration() {
+        for (secs, expected) in [
+            (0, "0s"),
+            (59, "59s"),
+            (125, "2m"),
+            (3 * 3_600, "3h"),
+            (2 * 86_400 + 5, "2d"),
+            (15 * 86_400, "2w"),
+            (800 * 86_400, "2y"),
+        ] {
+            assert_eq!(
+                format_relative_duration(Duration::from_secs(secs)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_shorten_branch() {
+        let branch = "feature/PROJ-1234-some-very-long-description";
+        assert_eq!(shorten_branch(branch, &BranchConfig::default()), branch);
+
+        let strip = BranchConfig {
+            strip_prefixes: Some(vec!["bugfix/".to_string(), "feature/".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            shorten_branch(branch, &strip),
+            "PROJ-1234-some-very-long-description"
+        );
+        assert_eq!(shorten_branch("feature/", &strip), "feature/");
+
+        let ticket = BranchConfig {
+            ticket_pattern: Some("/([A-Z]+-[0-9]+)-".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(shorten_branch(branch, &ticket), "PROJ-1234");
+        assert_eq!(shorten_branch("main", &ticket), "main");
+
+        let truncate = BranchConfig {
+            max_length: Some(16),
+            ..strip
+        };
+        assert_eq!(shorten_branch(branch, &truncate), "PROJ-123…ription");
+    }
+
+    #[test]
+    fn test_truncate_middle() {
+        assert_eq!(truncate_middle("main", 10), "main");
+        assert_eq!(truncate_middle("abcdefghij", 5), "ab…ij");
+        assert_eq!(truncate_middle("abcdefghij", 1), "…");
+        assert_eq!(truncate_middle("日本語のブランチ", 7), "日本…チ");
+    }
+
+    #[test]
+    fn test_format_git_prompt_line_mini_shows_compact_branch() {
+        let indicators = GitIndicators {
+            compact_branch: Some("PROJ-1234".to_string()),
+            ..make_dirty_indicators()
+        };
+        let result = format_git_prompt_line(
+            GitDisplayMode::Mini,
+            Some("git@email"),
+            "repo",
+            "PROJ-1234",
+            "PROJ-1234",
+            &indicators,
+            &["dir"],
+            &make_test_colors(),
+            &SegmentLayout::default(),
+        );
+        assert_eq!(strip_ansi(&result), "git@email: [repo : PROJ-1234 *] dir");
+        assert_eq!(
+            calculate_git_prompt_width(
+                GitDisplayMode::Mini,
+                Some("git@email"),
+                "repo",
+                "PROJ-1234",
+                &indicators,
+                &["dir"],
+                &make_test_colors(),
+                &SegmentLayout::default(),
+            ),
+            visual_width(&strip_ansi(&result))
+        );
+    }
+
     #[test]
     fn wrap_ansi_for_readline_bash_wraps_escape_sequences() {
         let input = "\x1b[38;2;0;116;217mhello\x1b[0m world";
@@ -0,0 +1,435 @@
+//! Prompt templates.
+//!
+//! A template lays out the whole prompt, e.g.
+//! `{user}@{host}:{dir}{git? [{repo}:{branch}]}\n└─ {exit} {symbol} `:
+//!
+//! - `{name}` is replaced by the value of a variable.
+//! - `{name? ...}` is a conditional group, rendered only when the variable
+//!   is not empty.
+//! - `{#Red bold}` paints the text that follows in a color and text style,
+//!   up to the next style directive, `{#}` or the end of the group.
+//! - `\{`, `\}` and `\\` are a literal brace and backslash, and `\n` is a
+//!   newline.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use anyhow::{Result, anyhow};
+use owo_colors::{OwoColorize, Style};
+
+use crate::clrs::Clrs;
+
+/// Variables every template can use; command and env segments add their
+/// names.
+pub const VARIABLES: &[&str] = &[
+    "line", "user", "host", "dir", "email", "git", "repo", "branch", "exit", "symbol",
+];
+
+/// Position in a template, for error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            1 => write!(f, "column {}", self.column),
+            line => write!(f, "line {}, column {}", line, self.column),
+        }
+    }
+}
+
+/// Value of a template variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Text painted in the active style directive, or in `style` outside
+    /// one.
+    Text { text: String, style: Option<Style> },
+    /// Text with colors of its own, which style directives leave alone.
+    Painted(String),
+}
+
+impl Value {
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text { text, .. } => text.is_empty(),
+            Value::Painted(text) => text.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Variable {
+        name: String,
+        position: Position,
+    },
+    Group {
+        name: String,
+        position: Position,
+        body: Vec<Node>,
+    },
+    /// Style of the text that follows; `None` resets it.
+    Style(Option<Style>),
+}
+
+/// A parsed prompt template.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    /// Parse a template. Errors give the position of the problem, e.g.
+    /// `Invalid template at column 12: unclosed {`.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parser = Parser {
+            chars: template.chars().peekable(),
+            position: Position { line: 1, column: 1 },
+        };
+        Ok(Self {
+            nodes: parser.nodes(None)?,
+        })
+    }
+
+    /// Variables the template uses, with their positions.
+    pub fn variables(&self) -> Vec<(&str, Position)> {
+        let mut variables = Vec::new();
+        collect_variables(&self.nodes, &mut variables);
+        variables
+    }
+
+    /// Render the template. Variables missing from `values` are empty.
+    pub fn render(&self, values: &HashMap<&str, Value>) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, values, None, &mut output);
+        output
+    }
+}
+
+fn collect_variables<'a>(nodes: &'a [Node], variables: &mut Vec<(&'a str, Position)>) {
+    for node in nodes {
+        match node {
+            Node::Variable { name, position } => variables.push((name, *position)),
+            Node::Group {
+                name,
+                position,
+                body,
+            } => {
+                variables.push((name, *position));
+                collect_variables(body, variables);
+            }
+            Node::Text(_) | Node::Style(_) => {}
+        }
+    }
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    values: &HashMap<&str, Value>,
+    mut style: Option<Style>,
+    output: &mut String,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => push(output, text, style),
+            Node::Variable { name, .. } => match values.get(name.as_str()) {
+                Some(Value::Text {
+                    text,
+                    style: own_style,
+                }) => push(output, text, style.or(*own_style)),
+                Some(Value::Painted(text)) => output.push_str(text),
+                None => {}
+            },
+            Node::Group { name, body, .. } => {
+                if values
+                    .get(name.as_str())
+                    .is_some_and(|value| !value.is_empty())
+                {
+                    render_nodes(body, values, style, output);
+                }
+            }
+            Node::Style(new_style) => style = *new_style,
+        }
+    }
+}
+
+fn push(output: &mut String, text: &str, style: Option<Style>) {
+    match style {
+        Some(style) if !text.is_empty() => output.push_str(&text.style(style).to_string()),
+        _ => output.push_str(text),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// Position of the next character.
+    position: Position,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        match c {
+            '\n' => {
+                self.position.line += 1;
+                self.position.column = 1;
+            }
+            _ => self.position.column += 1,
+        }
+        Some(c)
+    }
+
+    /// Parse up to the end of the template, or up to the `}` closing the
+    /// group opened at `group`.
+    fn nodes(&mut self, group: Option<Position>) -> Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        loop {
+            let position = self.position;
+            match self.next() {
+                None => match group {
+                    Some(open) => return Err(error(open, "unclosed {")),
+                    None => break,
+                },
+                Some('}') if group.is_some() => break,
+                Some('}') => return Err(error(position, "unexpected }")),
+                Some('\\') => text.push(self.escape(position)?),
+                Some('{') => {
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    nodes.push(self.placeholder(position)?);
+                }
+                Some(c) => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(nodes)
+    }
+
+    fn escape(&mut self, position: Position) -> Result<char> {
+        match self.next() {
+            Some('n') => Ok('\n'),
+            Some(c @ ('{' | '}' | '\\')) => Ok(c),
+            Some(c) => Err(error(position, &format!("unknown escape \\{}", c))),
+            None => Err(error(position, "trailing \\")),
+        }
+    }
+
+    /// Parse a variable, group or style directive after its `{`.
+    fn placeholder(&mut self, open: Position) -> Result<Node> {
+        if self.chars.peek() == Some(&'#') {
+            self.next();
+            return self.style(open);
+        }
+        let position = self.position;
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek()
+            && (c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            name.push(c);
+            self.next();
+        }
+        if name.is_empty() {
+            return Err(error(position, "expected a variable name"));
+        }
+        let end = self.position;
+        match self.next() {
+            Some('}') => Ok(Node::Variable { name, position }),
+            Some('?') => Ok(Node::Group {
+                name,
+                position,
+                body: self.nodes(Some(open))?,
+            }),
+            Some(_) => Err(error(end, &format!("expected }} or ? after {}", name))),
+            None => Err(error(open, "unclosed {")),
+        }
+    }
+
+    /// Parse a style directive after its `{#`: a color, text styles, both,
+    /// or nothing to reset the style.
+    fn style(&mut self, open: Position) -> Result<Node> {
+        let mut directive = String::new();
+        loop {
+            match self.next() {
+                Some('}') => break,
+                Some(c) => directive.push(c),
+                None => return Err(error(open, "unclosed {")),
+            }
+        }
+        if directive.trim().is_empty() {
+            return Ok(Node::Style(None));
+        }
+        let mut style = Style::new();
+        for word in directive.split_whitespace() {
+            style = match word {
+                "bold" => style.bold(),
+                "dimmed" => style.dimmed(),
+                "italic" => style.italic(),
+                "underline" => style.underline(),
+                color => match color.parse::<Clrs>() {
+                    Ok(color) => style.color(color.to_dyn()),
+                    Err(_) => {
+                        return Err(error(open, &format!("unknown color or style {}", word)));
+                    }
+                },
+            };
+        }
+        Ok(Node::Style(Some(style)))
+    }
+}
+
+fn error(position: Position, message: &str) -> anyhow::Error {
+    anyhow!("Invalid template at {}: {}", position, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&'static str, &str)]) -> HashMap<&'static str, Value> {
+        pairs
+            .iter()
+            .map(|(name, text)| {
+                let value = Value::Text {
+                    text: text.to_string(),
+                    style: None,
+                };
+                (*name, value)
+            })
+            .collect()
+    }
+
+    fn render(template: &str, pairs: &[(&'static str, &str)]) -> String {
+        Template::parse(template)
+            .expect("template")
+            .render(&values(pairs))
+    }
+
+    #[test]
+    fn test_render_variables_and_groups() {
+        let template = "{user}@{host}:{dir}{git? [{repo}:{branch}]}\n└─ {exit} {symbol} ";
+        let mut pairs = vec![
+            ("user", "alice"),
+            ("host", "devbox"),
+            ("dir", "~/src"),
+            ("exit", "0"),
+            ("symbol", "$"),
+        ];
+        assert_eq!(render(template, &pairs), "alice@devbox:~/src\n└─ 0 $ ");
+
+        pairs.extend([
+            ("git", "[pulse : main]"),
+            ("repo", "pulse"),
+            ("branch", "main"),
+        ]);
+        assert_eq!(
+            render(template, &pairs),
+            "alice@devbox:~/src [pulse:main]\n└─ 0 $ "
+        );
+        assert_eq!(render("{git?{missing}}", &[("git", "")]), "");
+    }
+
+    #[test]
+    fn test_render_escapes() {
+        assert_eq!(
+            render(r"\{user\} \\ {user}\n", &[("user", "alice")]),
+            "{user} \\ alice\n"
+        );
+    }
+
+    #[test]
+    fn test_render_style_directives() {
+        let red = Style::new().color(Clrs::Red.to_dyn()).bold();
+        let blue = Style::new().color(Clrs::Blue.to_dyn());
+        let mut values = values(&[("exit", "1")]);
+        values.insert(
+            "user",
+            Value::Text {
+                text: "alice".to_string(),
+                style: Some(blue),
+            },
+        );
+        values.insert("line", Value::Painted("\x1b[32mline\x1b[39m".to_string()));
+        let render = |template: &str| Template::parse(template).expect("template").render(&values);
+
+        assert_eq!(
+            render("{user} {#Red bold}{exit}{#} {exit}"),
+            format!("{} {} 1", "alice".style(blue), "1".style(red))
+        );
+        assert_eq!(
+            render("{#Red bold}{exit? [{user}]} {line}"),
+            format!(
+                "{}{}{}{}\x1b[32mline\x1b[39m",
+                " [".style(red),
+                "alice".style(red),
+                "]".style(red),
+                " ".style(red)
+            )
+        );
+        assert_eq!(
+            render("{exit? {#Red bold}x}{exit}"),
+            format!(" {}1", "x".style(red))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_report_position() {
+        let error = |template: &str| {
+            Template::parse(template)
+                .expect_err("parse error")
+                .to_string()
+        };
+
+        assert_eq!(
+            error("{user}@{host"),
+            "Invalid template at column 8: unclosed {"
+        );
+        assert_eq!(
+            error("{git? [{repo}]"),
+            "Invalid template at column 1: unclosed {"
+        );
+        assert_eq!(
+            error("{dir}}"),
+            "Invalid template at column 6: unexpected }"
+        );
+        assert_eq!(
+            error("{}"),
+            "Invalid template at column 2: expected a variable name"
+        );
+        assert_eq!(
+            error("{user name}"),
+            "Invalid template at column 6: expected } or ? after user"
+        );
+        assert_eq!(
+            error("{dir}\n{#Crimson}$"),
+            "Invalid template at line 2, column 1: unknown color or style Crimson"
+        );
+        assert_eq!(
+            error(r"\q"),
+            r"Invalid template at column 1: unknown escape \q"
+        );
+        assert_eq!(error("$ \\"), r"Invalid template at column 3: trailing \");
+    }
+
+    #[test]
+    fn test_variables() {
+        let template = Template::parse("{user}\n{git? {branch}}").expect("template");
+        assert_eq!(
+            template.variables(),
+            [
+                ("user", Position { line: 1, column: 2 }),
+                ("git", Position { line: 2, column: 2 }),
+                ("branch", Position { line: 2, column: 8 }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Trust for repositories owned by other users.
+//!
+//! Mirrors git's `safe.directory` semantics: a repository whose git directory
+//! is owned by another user is only trusted when a `safe.directory` entry in
+//! the system or global git config lists it. Untrusted repositories are
+//! opened without following config includes, and nothing is taken from their
+//! local config.
+
+use std::path::{Path, PathBuf};
+
+use gix::config::source::Kind;
+use gix::sec::Trust;
+use gix::sec::trust::DefaultForLevel;
+
+use crate::identity::expand_home;
+
+/// Options for opening a repository owned by another user: config includes
+/// are not followed, so the repository can't pull in other files.
+pub fn untrusted_options() -> gix::open::Options {
+    let mut options = gix::open::Options::default_for_level(Trust::Reduced);
+    options.permissions.config.includes = false;
+    options
+}
+
+/// Options for opening a trusted repository.
+pub fn trusted_options() -> gix::open::Options {
+    gix::open::Options::default_for_level(Trust::Full)
+}
+
+/// Whether `path` is owned by the current user.
+pub fn is_owned_by_current_user(path: &Path) -> bool {
+    gix::sec::identity::is_path_owned_by_current_user(path).unwrap_or(false)
+}
+
+/// Whether a `safe.directory` entry from the system or global git config
+/// lists the repository's work tree, or its git directory if it is bare.
+pub fn is_safe_directory(repo: &gix::Repository) -> bool {
+    let dir = repo.work_dir().unwrap_or(repo.git_dir());
+    let Ok(dir) = std::fs::canonicalize(dir) else {
+        return false;
+    };
+    let config = repo.config_snapshot();
+    let entries = config
+        .plumbing()
+        .strings_filter("safe", None, "directory", &mut |meta| {
+            matches!(meta.source.kind(), Kind::System | Kind::Global)
+        })
+        .unwrap_or_default();
+    safe_directory_matches(entries.iter().map(|entry| entry.to_string()), &dir)
+}
+
+/// Match `dir` against `safe.directory` entries like git does: `*` trusts
+/// every repository, an empty entry resets the list, and an entry ending in
+/// `/*` trusts every repository below it.
+fn safe_directory_matches(entries: impl IntoIterator<Item = String>, dir: &Path) -> bool {
+    let mut is_safe = false;
+    for entry in entries {
+        is_safe = match entry.as_str() {
+            "*" => true,
+            "" => false,
+            entry => {
+                is_safe
+                    || match entry.strip_suffix("/*") {
+                        Some(parent) => dir.starts_with(normalize(parent)),
+                        None => dir == normalize(entry),
+                    }
+            }
+        };
+    }
+    is_safe
+}
+
+fn normalize(entry: &str) -> PathBuf {
+    let path = PathBuf::from(expand_home(entry));
+    std::fs::canonicalize(&path).unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(entries: &[&str], dir: &str) -> bool {
+        safe_directory_matches(
+            entries.iter().map(|entry| entry.to_string()),
+            Path::new(dir),
+        )
+    }
+
+    #[test]
+    fn test_safe_directory_matches() {
+        assert!(matches(&["/srv/repo"], "/srv/repo"));
+        assert!(!matches(&["/srv/repo"], "/srv/repo/sub"));
+        assert!(!matches(&["/srv/repo"], "/srv/other"));
+        assert!(matches(&["/srv/*"], "/srv/repo"));
+        assert!(matches(&["*"], "/anywhere"));
+        assert!(!matches(&["*", ""], "/anywhere"));
+        assert!(matches(&["*", "", "/anywhere"], "/anywhere"));
+        assert!(!matches(&[], "/srv/repo"));
+    }
+}
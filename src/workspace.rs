@@ -0,0 +1,251 @@
+//! Workspace member detection for monorepos.
+//!
+//! Finds the workspace member containing a directory, based on the member
+//! lists declared by `Cargo.toml` (`[workspace]`), `package.json`
+//! (`workspaces`) and `go.work` (`use`).
+
+use glob::{MatchOptions, Pattern};
+use std::path::Path;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Member patterns declared by a workspace root.
+#[derive(Debug)]
+struct WorkspaceMembers {
+    /// File that marks a directory as a project, e.g. `Cargo.toml`.
+    manifest: &'static str,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl WorkspaceMembers {
+    fn new(manifest: &'static str) -> Self {
+        Self {
+            manifest,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, pattern: &str) {
+        let (list, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (&mut self.exclude, pattern),
+            None => (&mut self.include, pattern),
+        };
+        let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+        let pattern = pattern.trim_end_matches('/');
+        if let Ok(pattern) = Pattern::new(pattern) {
+            list.push(pattern);
+        }
+    }
+
+    /// Whether `path`, relative to the workspace root, is a member.
+    fn contains(&self, root: &Path, path: &Path) -> bool {
+        let matches = |pattern: &Pattern| pattern.matches_path_with(path, MATCH_OPTIONS);
+        self.include.iter().any(matches)
+            && !self.exclude.iter().any(matches)
+            && root.join(path).join(self.manifest).is_file()
+    }
+}
+
+/// Find the name of the workspace member that contains `start`.
+///
+/// Directories are searched from `start` up to and including `root`. The
+/// nearest directory that declares a workspace decides membership, and the
+/// member's directory name is returned.
+pub fn find_workspace_member(start: &Path, root: &Path) -> Option<String> {
+    if !start.starts_with(root) {
+        return None;
+    }
+
+    let mut dir = start;
+    loop {
+        if let Some(members) = read_workspace_members(dir) {
+            let relative = start.strip_prefix(dir).ok()?;
+            return relative
+                .ancestors()
+                .filter(|candidate| !candidate.as_os_str().is_empty())
+                .find(|candidate| members.contains(dir, candidate))
+                .and_then(|member| member.file_name()?.to_str())
+                .map(str::to_string);
+        }
+        if dir == root {
+            return None;
+        }
+        dir = dir.parent()?;
+    }
+}
+
+fn read_workspace_members(dir: &Path) -> Option<WorkspaceMembers> {
+    read_cargo_workspace(dir)
+        .or_else(|| read_npm_workspace(dir))
+        .or_else(|| read_go_workspace(dir))
+}
+
+fn read_cargo_workspace(dir: &Path) -> Option<WorkspaceMembers> {
+    let content = std::fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let manifest: toml::Table = content.parse().ok()?;
+    let workspace = manifest.get("workspace")?.as_table()?;
+
+    let mut members = WorkspaceMembers::new("Cargo.toml");
+    for (key, negate) in [("members", false), ("exclude", true)] {
+        let patterns = workspace.get(key).and_then(toml::Value::as_array);
+        for pattern in patterns
+            .into_iter()
+            .flatten()
+            .filter_map(toml::Value::as_str)
+        {
+            if negate {
+                members.push(&format!("!{}", pattern));
+            } else {
+                members.push(pattern);
+            }
+        }
+    }
+    Some(members)
+}
+
+fn read_npm_workspace(dir: &Path) -> Option<WorkspaceMembers> {
+    let content = std::fs::read_to_string(dir.join("package.json")).ok()?;
+    let manifest: serde_json::Value = serde_json::from_str(&content).ok()?;
+    let workspaces = manifest.get("workspaces")?;
+    // Either a plain list or `{ "packages": [...] }` (Yarn).
+    let patterns = workspaces
+        .as_array()
+        .or_else(|| workspaces.get("packages")?.as_array())?;
+
+    let mut members = WorkspaceMembers::new("package.json");
+    for pattern in patterns.iter().filter_map(serde_json::Value::as_str) {
+        members.push(pattern);
+    }
+    Some(members)
+}
+
+fn read_go_workspace(dir: &Path) -> Option<WorkspaceMembers> {
+    let content = std::fs::read_to_string(dir.join("go.work")).ok()?;
+
+    let mut members = WorkspaceMembers::new("go.mod");
+    let mut in_use_block = false;
+    for line in content.lines() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        if in_use_block {
+            if line == ")" {
+                in_use_block = false;
+            } else if !line.is_empty() {
+                members.push(line);
+            }
+        } else if let Some(rest) = line.strip_prefix("use")
+            && rest.starts_with([' ', '\t', '('])
+        {
+            match rest.trim() {
+                "(" => in_use_block = true,
+                path if !path.is_empty() => members.push(path),
+                _ => {}
+            }
+        }
+    }
+    Some(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_file(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().expect("parent")).expect("create dirs");
+        std::fs::write(path, content).expect("write file");
+    }
+
+    fn create_dir(root: &Path, path: &str) -> std::path::PathBuf {
+        let path = root.join(path);
+        std::fs::create_dir_all(&path).expect("create dir");
+        path
+    }
+
+    #[test]
+    fn test_cargo_workspace_member() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let root = temp_dir.path();
+        write_file(
+            root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/legacy\"]\n",
+        );
+        write_file(
+            root,
+            "crates/core/Cargo.toml",
+            "[package]\nname = \"core\"\n",
+        );
+        let start = create_dir(root, "crates/core/src/bin");
+
+        assert_eq!(find_workspace_member(&start, root).as_deref(), Some("core"));
+
+        write_file(
+            root,
+            "crates/legacy/Cargo.toml",
+            "[package]\nname = \"legacy\"\n",
+        );
+        let excluded = create_dir(root, "crates/legacy/src");
+        assert_eq!(find_workspace_member(&excluded, root), None);
+        assert_eq!(find_workspace_member(root, root), None);
+    }
+
+    #[test]
+    fn test_npm_workspace_member() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let root = temp_dir.path();
+        write_file(
+            root,
+            "package.json",
+            r#"{ "workspaces": { "packages": ["packages/**", "!packages/private"] } }"#,
+        );
+        write_file(root, "packages/web/package.json", "{}");
+        write_file(root, "packages/private/package.json", "{}");
+        let start = create_dir(root, "packages/web/src/components");
+
+        assert_eq!(find_workspace_member(&start, root).as_deref(), Some("web"));
+
+        let private = create_dir(root, "packages/private/src");
+        assert_eq!(find_workspace_member(&private, root), None);
+    }
+
+    #[test]
+    fn test_go_workspace_member() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let root = temp_dir.path();
+        write_file(
+            root,
+            "go.work",
+            "go 1.22\n\nuse (\n\t./cmd/api // service\n\t./lib\n)\nuse ./tools\n",
+        );
+        write_file(root, "cmd/api/go.mod", "module example.com/api\n");
+        write_file(root, "tools/go.mod", "module example.com/tools\n");
+        let api = create_dir(root, "cmd/api/internal");
+        let tools = create_dir(root, "tools");
+        let other = create_dir(root, "docs");
+
+        assert_eq!(find_workspace_member(&api, root).as_deref(), Some("api"));
+        assert_eq!(
+            find_workspace_member(&tools, root).as_deref(),
+            Some("tools")
+        );
+        assert_eq!(find_workspace_member(&other, root), None);
+    }
+
+    #[test]
+    fn test_no_workspace_above_start() {
+        let temp_dir = TempDir::new().expect("temp dir");
+        let root = temp_dir.path();
+        write_file(root, "Cargo.toml", "[package]\nname = \"single\"\n");
+        let start = create_dir(root, "src");
+
+        assert_eq!(find_workspace_member(&start, root), None);
+        assert_eq!(find_workspace_member(root, &start), None);
+    }
+}
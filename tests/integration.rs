@@ -42,3 +42,81 @@ fn test_pulse_binary_runs_and_outputs_prompt() {
         "Output should reflect exit code or dualline format"
     );
 }
+
+/// Run pulse in `dir` with `config`, killing it if it hasn't finished after
+/// `limit`. Returns its stdout, or `None` if it had to be killed.
+#[cfg(unix)]
+fn run_pulse_with_limit(
+    dir: &std::path::Path,
+    config: &std::path::Path,
+    limit: std::time::Duration,
+) -> Option<String> {
+    use std::process::Stdio;
+    use std::time::Instant;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pulse"))
+        .arg("--config")
+        .arg(config)
+        .current_dir(dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run pulse binary");
+    let start = Instant::now();
+    while child
+        .try_wait()
+        .expect("Failed to wait for pulse")
+        .is_none()
+    {
+        if start.elapsed() > limit {
+            child.kill().expect("Failed to kill pulse");
+            child.wait().expect("Failed to wait for pulse");
+            return None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let output = child
+        .wait_with_output()
+        .expect("Failed to read pulse output");
+    assert!(output.status.success(), "Pulse binary exited with error");
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_time_budget_bounds_a_hanging_repository() {
+    use std::time::{Duration, Instant};
+
+    // A FIFO in place of the index makes every read of it block, like a
+    // hung network filesystem would.
+    let temp_dir = tempfile::tempdir().expect("create temp dir");
+    let repo_dir = temp_dir.path().join("slow");
+    gix::init(&repo_dir).expect("init git repo");
+    let mkfifo = Command::new("mkfifo")
+        .arg(repo_dir.join(".git").join("index"))
+        .status()
+        .expect("Failed to run mkfifo");
+    assert!(mkfifo.success(), "mkfifo failed");
+
+    let unbounded = temp_dir.path().join("unbounded.yaml");
+    std::fs::write(&unbounded, "segments: []\n").expect("write config");
+    let output = run_pulse_with_limit(&repo_dir, &unbounded, Duration::from_secs(1));
+    assert_eq!(
+        output, None,
+        "Without a time budget the repository should hang"
+    );
+
+    let bounded = temp_dir.path().join("bounded.yaml");
+    std::fs::write(&bounded, "segments: []\ngit:\n  time_budget: 200ms\n").expect("write config");
+    let start = Instant::now();
+    let output = run_pulse_with_limit(&repo_dir, &bounded, Duration::from_secs(10))
+        .expect("Pulse should finish within its time budget");
+    assert!(
+        start.elapsed() < Duration::from_secs(5),
+        "Pulse took {:?}",
+        start.elapsed()
+    );
+    assert!(
+        output.contains('⧗'),
+        "Output should mark the git block as partial"
+    );
+}